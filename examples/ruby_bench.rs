@@ -0,0 +1,117 @@
+//! Times ruby placement on a synthetic book: `cargo run --release --example ruby_bench [paragraphs]`.
+//!
+//! The book is random kanji and kana with a ruby on every word, and the subtitles are its
+//! sentences with a few chars dropped, the way a transcription misses some.
+
+use std::time::Instant;
+
+use audiobook_convert::align::LineAlignment;
+use audiobook_convert::epub_text::EpubText;
+use audiobook_convert::markup::Span;
+use audiobook_convert::overrides::{Override, OverrideAction, OverrideScope, Overrides};
+use audiobook_convert::place_rubies;
+
+const KANJI: &str =
+    "日本語漢字東京大学先生学校時間電車図書館自然言語処理音声文章物語主人公世界魔法";
+const KANA: &str = "のはがをにでともへからまでよりだですますた";
+const PARAGRAPHS_PER_CHAPTER: usize = 200;
+
+/// Small deterministic generator, so runs can be compared.
+struct Lcg(u64);
+
+impl Lcg {
+    fn next(&mut self, n: usize) -> usize {
+        self.0 = self
+            .0
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        ((self.0 >> 33) % n as u64) as usize
+    }
+}
+
+fn main() {
+    let paragraphs: usize = std::env::args()
+        .nth(1)
+        .and_then(|n| n.parse().ok())
+        .unwrap_or(10_000);
+    let kanji: Vec<char> = KANJI.chars().collect();
+    let kana: Vec<char> = KANA.chars().collect();
+    let mut rng = Lcg(42);
+
+    let mut chapters = vec![];
+    let mut lines = vec![];
+    let mut html = String::new();
+    for p in 0..paragraphs {
+        html.push_str("<p>");
+        for _ in 0..3 {
+            let mut sentence = String::new();
+            for _ in 0..4 + rng.next(4) {
+                let word: String = (0..1 + rng.next(3))
+                    .map(|_| kanji[rng.next(kanji.len())])
+                    .collect();
+                let reading: String = (0..word.chars().count() * 2)
+                    .map(|_| kana[rng.next(kana.len())])
+                    .collect();
+                let particle = kana[rng.next(kana.len())];
+                html.push_str(&format!("<ruby>{word}<rt>{reading}</rt></ruby>{particle}"));
+                sentence.push_str(&word);
+                sentence.push(particle);
+            }
+            html.push('。');
+            sentence.push('。');
+            let line: String = sentence.chars().filter(|_| rng.next(50) != 0).collect();
+            lines.push(line);
+        }
+        html.push_str("</p>");
+        if (p + 1) % PARAGRAPHS_PER_CHAPTER == 0 || p + 1 == paragraphs {
+            chapters.push(format!("<html><body>{html}</body></html>"));
+            html.clear();
+        }
+    }
+
+    let start = Instant::now();
+    let mut book = EpubText::default();
+    for chapter in &chapters {
+        book.push_html(chapter);
+    }
+    println!(
+        "parsed {} chapters, {} chars, {} rubies in {:?}",
+        chapters.len(),
+        book.text.chars().count(),
+        book.rubies.len(),
+        start.elapsed()
+    );
+
+    let start = Instant::now();
+    let alignment = LineAlignment::new(&book.text, &lines);
+    println!("aligned {} lines in {:?}", lines.len(), start.elapsed());
+
+    let start = Instant::now();
+    let mut spans: Vec<Vec<Span>> = vec![vec![]; lines.len()];
+    let unplaced = place_rubies(&alignment, &book, &mut spans);
+    println!(
+        "placed {}/{} rubies in {:?}",
+        book.rubies.len() - unplaced.len(),
+        book.rubies.len(),
+        start.elapsed()
+    );
+
+    let overrides = Overrides {
+        entries: (0..1000)
+            .map(|n| Override {
+                base: (0..2).map(|_| kanji[rng.next(kanji.len())]).collect(),
+                action: OverrideAction::Reading(format!("よみ{n}")),
+                scope: OverrideScope::Everywhere,
+            })
+            .collect(),
+        warnings: vec![],
+    };
+    let line_chapters = vec![0; lines.len()];
+    let start = Instant::now();
+    overrides.apply(&lines, &line_chapters, &mut spans);
+    println!(
+        "applied {} overrides in {:?}",
+        overrides.entries.len(),
+        start.elapsed()
+    );
+}
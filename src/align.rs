@@ -0,0 +1,420 @@
+use std::collections::HashMap;
+use std::ops::Range;
+use unicode_normalization::UnicodeNormalization;
+
+/// Gram size of the first anchoring pass, halved on each recursion.
+const ANCHOR_GRAM: usize = 12;
+/// Gaps with fewer cells than this get a full LCS instead of more anchoring.
+const FULL_DP_CELLS: usize = 1_000_000;
+/// Half width of the band used on gaps too large for a full LCS.
+const BAND: usize = 64;
+/// Gaps past this many banded cells are left unaligned.
+const MAX_BAND_CELLS: usize = 20_000_000;
+/// How many letters away from a point the closest matched one can be.
+const POINT_SEARCH: usize = 8;
+/// How far (in letters) an anchor looks for matched text around a point between lines.
+const ANCHOR_SEARCH: usize = 2000;
+/// Letters of unmatched text between an anchor and its point that halve the confidence.
+const ANCHOR_SLACK: f32 = 50.0;
+
+/// Text reduced to what survives between the book and the subtitles: NFKC, lowercase, letters
+/// and digits only. `origin` is the char index each kept char came from.
+#[derive(Debug, Default)]
+pub struct Normalized {
+    pub chars: Vec<char>,
+    pub origin: Vec<usize>,
+}
+
+impl Normalized {
+    pub fn push_str(&mut self, text: &str) {
+        for (i, c) in text.chars().enumerate() {
+            for n in std::iter::once(c).nfkc().flat_map(char::to_lowercase) {
+                if n.is_alphanumeric() {
+                    self.chars.push(n);
+                    self.origin.push(i);
+                }
+            }
+        }
+    }
+
+    /// Normalised chars coming from the original chars in `span`.
+    pub fn range_of(&self, span: Range<usize>) -> Range<usize> {
+        self.origin.partition_point(|o| *o < span.start)
+            ..self.origin.partition_point(|o| *o < span.end)
+    }
+}
+
+pub fn normalize(text: &str) -> Normalized {
+    let mut normalized = Normalized::default();
+    normalized.push_str(text);
+    normalized
+}
+
+/// Plain LCS over the whole gap, for small gaps.
+fn lcs(a: &[char], b: &[char], a_off: usize, b_off: usize, out: &mut [Option<usize>]) {
+    let w = b.len() + 1;
+    let mut dp = vec![0u32; (a.len() + 1) * w];
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            dp[i * w + j] = if a[i - 1] == b[j - 1] {
+                dp[(i - 1) * w + j - 1] + 1
+            } else {
+                dp[(i - 1) * w + j].max(dp[i * w + j - 1])
+            };
+        }
+    }
+    let (mut i, mut j) = (a.len(), b.len());
+    while i > 0 && j > 0 {
+        if a[i - 1] == b[j - 1] {
+            out[a_off + i - 1] = Some(b_off + j - 1);
+            i -= 1;
+            j -= 1;
+        } else if dp[(i - 1) * w + j] >= dp[i * w + j - 1] {
+            i -= 1;
+        } else {
+            j -= 1;
+        }
+    }
+}
+
+/// LCS restricted to a band around the gap's diagonal, for gaps too large for [`lcs`].
+fn banded_lcs(a: &[char], b: &[char], a_off: usize, b_off: usize, out: &mut [Option<usize>]) {
+    let (la, lb) = (a.len(), b.len());
+    let centre = |i: usize| i * lb / la.max(1);
+    let bounds = |i: usize| {
+        let c = centre(i);
+        (c.saturating_sub(BAND), (c + BAND).min(lb))
+    };
+    let width = 2 * BAND + 1;
+    if (la + 1) * width > MAX_BAND_CELLS {
+        return;
+    }
+    // dp[i][j - lo(i)], cells outside the band count as 0.
+    let mut dp = vec![0u32; (la + 1) * width];
+    let get = |dp: &[u32], i: usize, j: usize| -> u32 {
+        let (lo, hi) = bounds(i);
+        if j < lo || j > hi {
+            0
+        } else {
+            dp[i * width + j - lo]
+        }
+    };
+    for i in 1..=la {
+        let (lo, hi) = bounds(i);
+        for j in lo.max(1)..=hi {
+            let value = if a[i - 1] == b[j - 1] {
+                get(&dp, i - 1, j - 1) + 1
+            } else {
+                get(&dp, i - 1, j).max(get(&dp, i, j - 1))
+            };
+            dp[i * width + j - lo] = value;
+        }
+    }
+    let (mut i, mut j) = (la, lb);
+    while i > 0 && j > 0 {
+        let (lo, hi) = bounds(i);
+        if j < lo || j > hi {
+            break;
+        }
+        if a[i - 1] == b[j - 1] {
+            out[a_off + i - 1] = Some(b_off + j - 1);
+            i -= 1;
+            j -= 1;
+        } else if get(&dp, i - 1, j) >= get(&dp, i, j - 1) {
+            i -= 1;
+        } else {
+            j -= 1;
+        }
+    }
+}
+
+/// Longest increasing subsequence of `pairs` by their second element.
+fn increasing_chain(pairs: &[(usize, usize)]) -> Vec<(usize, usize)> {
+    let mut tails: Vec<usize> = vec![];
+    let mut previous: Vec<Option<usize>> = vec![None; pairs.len()];
+    for (n, (_, b)) in pairs.iter().enumerate() {
+        let pos = tails.partition_point(|t| pairs[*t].1 < *b);
+        if pos > 0 {
+            previous[n] = Some(tails[pos - 1]);
+        }
+        if pos == tails.len() {
+            tails.push(n);
+        } else {
+            tails[pos] = n;
+        }
+    }
+    let mut chain = vec![];
+    let mut current = tails.last().copied();
+    while let Some(n) = current {
+        chain.push(pairs[n]);
+        current = previous[n];
+    }
+    chain.reverse();
+    chain
+}
+
+/// Grams of size `k` appearing exactly once in `text`, with their position.
+fn unique_grams(text: &[char], k: usize) -> HashMap<&[char], Option<usize>> {
+    let mut grams: HashMap<&[char], Option<usize>> = HashMap::with_capacity(text.len());
+    for (i, gram) in text.windows(k).enumerate() {
+        grams
+            .entry(gram)
+            .and_modify(|e| *e = None)
+            .or_insert(Some(i));
+    }
+    grams
+}
+
+fn align_gap(
+    a: &[char],
+    b: &[char],
+    a_range: Range<usize>,
+    b_range: Range<usize>,
+    k: usize,
+    out: &mut [Option<usize>],
+) {
+    let (sa, sb) = (&a[a_range.clone()], &b[b_range.clone()]);
+    if sa.is_empty() || sb.is_empty() {
+        return;
+    }
+    if sa.len() * sb.len() <= FULL_DP_CELLS {
+        lcs(sa, sb, a_range.start, b_range.start, out);
+        return;
+    }
+    if k < 2 || sa.len() < k || sb.len() < k {
+        banded_lcs(sa, sb, a_range.start, b_range.start, out);
+        return;
+    }
+
+    let grams_b = unique_grams(sb, k);
+    let pairs: Vec<(usize, usize)> = unique_grams(sa, k)
+        .into_iter()
+        .filter_map(|(gram, i)| Some((i?, (*grams_b.get(gram)?)?)))
+        .collect::<std::collections::BTreeMap<_, _>>()
+        .into_iter()
+        .collect();
+    let chain = increasing_chain(&pairs);
+    if chain.is_empty() {
+        align_gap(a, b, a_range, b_range, k / 2, out);
+        return;
+    }
+
+    // Anchored grams, then everything between two anchors gets aligned on its own.
+    let (mut next_a, mut next_b) = (0, 0);
+    for (i, j) in chain {
+        if i < next_a || j < next_b {
+            continue;
+        }
+        if i > next_a || j > next_b {
+            align_gap(
+                a,
+                b,
+                a_range.start + next_a..a_range.start + i,
+                b_range.start + next_b..b_range.start + j,
+                k / 2,
+                out,
+            );
+        }
+        let mut t = 0;
+        while i + t < sa.len() && j + t < sb.len() && sa[i + t] == sb[j + t] {
+            out[a_range.start + i + t] = Some(b_range.start + j + t);
+            t += 1;
+        }
+        (next_a, next_b) = (i + t, j + t);
+    }
+    align_gap(
+        a,
+        b,
+        a_range.start + next_a..a_range.end,
+        b_range.start + next_b..b_range.end,
+        k / 2,
+        out,
+    );
+}
+
+/// Character alignment between `a` and `b`: for each char of `a`, the char of `b` it was matched
+/// with. Unique grams found in both texts anchor the alignment, recursively with smaller grams,
+/// and the gaps left between anchors are filled with an LCS.
+pub fn align(a: &[char], b: &[char]) -> Vec<Option<usize>> {
+    let mut out = vec![None; a.len()];
+    align_gap(a, b, 0..a.len(), 0..b.len(), ANCHOR_GRAM, &mut out);
+    out
+}
+
+/// Alignment of the book's text onto the script lines, to find where something from the book
+/// ended up.
+pub struct LineAlignment {
+    book: Normalized,
+    lines: Normalized,
+    /// First normalised char of each line in `lines`.
+    line_starts: Vec<usize>,
+    book_to_lines: Vec<Option<usize>>,
+}
+
+impl LineAlignment {
+    pub fn new(book_text: &str, lines: &[String]) -> Self {
+        let book = normalize(book_text);
+        let mut normalized = Normalized::default();
+        let mut line_starts = Vec::with_capacity(lines.len());
+        for line in lines {
+            line_starts.push(normalized.chars.len());
+            normalized.push_str(line);
+        }
+        let book_to_lines = align(&book.chars, &normalized.chars);
+        Self {
+            book,
+            lines: normalized,
+            line_starts,
+            book_to_lines,
+        }
+    }
+
+    fn line_of(&self, n: usize) -> usize {
+        self.line_starts.partition_point(|s| *s <= n) - 1
+    }
+
+    /// The line a span of the book's text (in chars) is in, and where in that line, when every
+    /// letter of the span was matched contiguously inside a single line.
+    pub fn map_span(&self, span: Range<usize>) -> Option<(usize, Range<usize>)> {
+        let range = self.book.range_of(span);
+        if range.is_empty() {
+            return None;
+        }
+        let mapped: Vec<usize> = self.book_to_lines[range]
+            .iter()
+            .copied()
+            .collect::<Option<Vec<usize>>>()?;
+        let (first, last) = (mapped[0], *mapped.last().unwrap());
+        if last - first + 1 != mapped.len() {
+            return None;
+        }
+        let line = self.line_of(first);
+        if self.line_of(last) != line {
+            return None;
+        }
+        Some((line, self.lines.origin[first]..self.lines.origin[last] + 1))
+    }
+
+    /// Where a point between two chars of the book's text went: the line and the char of the
+    /// line it comes before, going by the closest matched letter on either side.
+    pub fn map_point(&self, position: usize) -> Option<(usize, usize)> {
+        let n = self.book.range_of(position..position).start;
+        let before = self.book_to_lines[..n]
+            .iter()
+            .rev()
+            .take(POINT_SEARCH)
+            .flatten()
+            .next()
+            .map(|b| (self.line_of(*b), self.lines.origin[*b] + 1));
+        let after = || {
+            self.book_to_lines[n..]
+                .iter()
+                .take(POINT_SEARCH)
+                .flatten()
+                .next()
+                .map(|b| (self.line_of(*b), self.lines.origin[*b]))
+        };
+        before.or_else(after)
+    }
+
+    /// Where a point of the book's text falls between the lines: the line it comes before (the
+    /// number of lines when it comes after the last), and how sure that is, from 0 to 1. The
+    /// confidence drops with the unmatched text around the point, and when the text before and
+    /// after it went to lines that aren't next to each other.
+    pub fn anchor_point(&self, position: usize) -> Option<(usize, f32)> {
+        let n = self.book.range_of(position..position).start;
+        let before = self.book_to_lines[n.saturating_sub(ANCHOR_SEARCH)..n]
+            .iter()
+            .rev()
+            .enumerate()
+            .find_map(|(d, b)| Some((d, self.line_of((*b)?))));
+        let after = self.book_to_lines[n..(n + ANCHOR_SEARCH).min(self.book_to_lines.len())]
+            .iter()
+            .enumerate()
+            .find_map(|(d, b)| Some((d, self.line_of((*b)?))));
+        let proximity = |d: usize| 1.0 / (1.0 + d as f32 / ANCHOR_SLACK);
+        match (before, after) {
+            (Some((db, lb)), Some((da, la))) => {
+                let line = if db <= da { lb + 1 } else { la };
+                let consistency = if la <= lb + 1 {
+                    1.0
+                } else {
+                    1.0 / (la - lb) as f32
+                };
+                Some((line, consistency * proximity(db.min(da))))
+            }
+            (Some((db, lb)), None) => Some((lb + 1, proximity(db))),
+            (None, Some((da, la))) => Some((la, proximity(da))),
+            (None, None) => None,
+        }
+    }
+
+    /// Where the matched letters of a span of the book's text (in chars) ended up, one range per
+    /// line they went to, for styles that can be split across lines.
+    pub fn map_span_pieces(&self, span: Range<usize>) -> Vec<(usize, Range<usize>)> {
+        let mut pieces: Vec<(usize, Range<usize>)> = vec![];
+        for n in self.book_to_lines[self.book.range_of(span)]
+            .iter()
+            .flatten()
+        {
+            let line = self.line_of(*n);
+            let origin = self.lines.origin[*n];
+            match pieces.last_mut() {
+                Some((last, range)) if *last == line && origin >= range.start => {
+                    range.end = range.end.max(origin + 1)
+                }
+                _ => pieces.push((line, origin..origin + 1)),
+            }
+        }
+        pieces
+    }
+
+    /// The line the book's text at or around a span (in chars) went to, for spans that
+    /// couldn't be mapped themselves.
+    pub fn nearest_line(&self, span: Range<usize>) -> Option<usize> {
+        let range = self.book.range_of(span);
+        let before = self.book_to_lines[..range.end]
+            .iter()
+            .rev()
+            .flatten()
+            .next();
+        let after = self.book_to_lines[range.start..].iter().flatten().next();
+        before.or(after).map(|n| self.line_of(*n))
+    }
+
+    /// For each line, the span of the book's text (in chars) its letters were matched with, and
+    /// the share of its letters that were matched, or of the span's letters when that's lower: a
+    /// few letters matched far apart make a span much longer than the line, and a poor match.
+    pub fn line_matches(&self) -> Vec<(Option<Range<usize>>, f32)> {
+        let mut lines_to_book = vec![None; self.lines.chars.len()];
+        for (a, b) in self.book_to_lines.iter().enumerate() {
+            if let Some(b) = b {
+                lines_to_book[*b] = Some(a);
+            }
+        }
+        let mut ends = self.line_starts[1..].to_vec();
+        ends.push(self.lines.chars.len());
+        self.line_starts
+            .iter()
+            .zip(ends)
+            .map(|(start, end)| {
+                let matched: Vec<usize> = lines_to_book[*start..end]
+                    .iter()
+                    .flatten()
+                    .copied()
+                    .collect();
+                match (matched.first(), matched.last()) {
+                    (Some(first), Some(last)) => {
+                        let line_share = matched.len() as f32 / (end - start) as f32;
+                        let span_share = matched.len() as f32 / (last - first + 1) as f32;
+                        (
+                            Some(self.book.origin[*first]..self.book.origin[*last] + 1),
+                            line_share.min(span_share),
+                        )
+                    }
+                    _ => (None, 0.0),
+                }
+            })
+            .collect()
+    }
+}
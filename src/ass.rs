@@ -0,0 +1,76 @@
+use regex::Regex;
+use srtlib::{Subtitle, Subtitles, Timestamp};
+
+/// `H:MM:SS.cc` time of an ASS event.
+fn parse_time(time: &str) -> Option<Timestamp> {
+    let (hours, rest) = time.trim().split_once(':')?;
+    let (minutes, rest) = rest.split_once(':')?;
+    let (seconds, centiseconds) = rest.split_once('.')?;
+    Some(Timestamp::new(
+        hours.parse().ok()?,
+        minutes.parse().ok()?,
+        seconds.parse().ok()?,
+        centiseconds.parse::<u16>().ok()? * 10,
+    ))
+}
+
+/// Reads the `Dialogue` events of an ASS/SSA script as subtitles. Override tags (`{\pos(…)}`…)
+/// are dropped, except on karaoke lines when `karaoke` is set, which keep them for their
+/// readings to be read, see [`super::inline_ruby::RubyNotations::ass_karaoke`].
+pub fn parse_ass(script: &str, karaoke: bool) -> Subtitles {
+    let tags = Regex::new(r"\{[^}]*\}").unwrap();
+    let karaoke_tag = Regex::new(r"\{[^}]*\\[kK][fo]?\d").unwrap();
+    // Columns of the events, the default ones of ASS when the script doesn't say.
+    let mut format: Vec<String> = [
+        "layer", "start", "end", "style", "name", "marginl", "marginr", "marginv", "effect", "text",
+    ]
+    .map(String::from)
+    .to_vec();
+    let mut in_events = false;
+    let mut subtitles = Subtitles::new();
+    for line in script.lines() {
+        let line = line.trim();
+        if line.starts_with('[') {
+            in_events = line.eq_ignore_ascii_case("[events]");
+            continue;
+        }
+        let Some((kind, value)) = line.split_once(':').filter(|_| in_events) else {
+            continue;
+        };
+        match kind.trim() {
+            "Format" => {
+                format = value
+                    .split(',')
+                    .map(|column| column.trim().to_lowercase())
+                    .collect()
+            }
+            "Dialogue" => {
+                // The text is last and can hold commas.
+                let fields: Vec<&str> = value.trim_start().splitn(format.len(), ',').collect();
+                let column = |name: &str| {
+                    let index = format.iter().position(|c| c == name)?;
+                    fields.get(index).copied()
+                };
+                let (Some(start), Some(end), Some(text)) = (
+                    column("start").and_then(parse_time),
+                    column("end").and_then(parse_time),
+                    column("text"),
+                ) else {
+                    continue;
+                };
+                let text = text
+                    .replace("\\N", "\n")
+                    .replace("\\n", "\n")
+                    .replace("\\h", " ");
+                let text = if karaoke && karaoke_tag.is_match(&text) {
+                    text
+                } else {
+                    tags.replace_all(&text, "").into_owned()
+                };
+                subtitles.push(Subtitle::new(subtitles.len() + 1, start, end, text));
+            }
+            _ => {}
+        }
+    }
+    subtitles
+}
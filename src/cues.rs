@@ -0,0 +1,333 @@
+use rayon::prelude::{IntoParallelRefMutIterator, ParallelIterator};
+use srtlib::{Subtitle, Timestamp};
+use std::path::Path;
+
+use super::ffmpeg_command;
+
+const SENTENCE_ENDERS: [char; 7] = ['。', '！', '？', '!', '?', '…', '‥'];
+/// Enders that are just as often a pause inside a sentence (`そう…だね`).
+const TRAILING_ENDERS: [char; 2] = ['…', '‥'];
+pub const OPENING_BRACKETS: [char; 12] = [
+    '「', '『', '（', '(', '【', '〈', '《', '〔', '“', '‘', '［', '[',
+];
+const CLOSING_BRACKETS: [char; 12] = [
+    '」', '』', '）', ')', '】', '〉', '》', '〕', '”', '’', '］', ']',
+];
+/// How far (ms) a split point may move to land in a silence.
+const SILENCE_SNAP_WINDOW: u32 = 1500;
+
+fn millis(t: Timestamp) -> u32 {
+    let (hours, mins, secs, millis) = t.get();
+    Timestamp::convert_to_milliseconds(hours, mins, secs, millis)
+}
+
+fn is_closing(c: char) -> bool {
+    CLOSING_BRACKETS.contains(&c) || c == '"' || c == '\''
+}
+
+fn ends_sentence(c: char, next: Option<char>) -> bool {
+    if !SENTENCE_ENDERS.contains(&c) {
+        return false;
+    }
+    if !TRAILING_ENDERS.contains(&c) {
+        return true;
+    }
+    next.is_none_or(|n| {
+        n.is_whitespace()
+            || OPENING_BRACKETS.contains(&n)
+            || SENTENCE_ENDERS.contains(&n) && !TRAILING_ENDERS.contains(&n)
+    })
+}
+
+/// Splits a cue's text after each sentence ender that isn't inside brackets, keeping the ender
+/// and any closing punctuation right after it with the sentence. Ellipses only end a sentence
+/// when followed by a space or a new quote.
+pub fn sentence_parts(text: &str) -> Vec<String> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut parts = vec![];
+    let mut current = String::new();
+    let mut depth = 0usize;
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        current.push(c);
+        i += 1;
+        if OPENING_BRACKETS.contains(&c) {
+            depth += 1;
+        } else if CLOSING_BRACKETS.contains(&c) {
+            depth = depth.saturating_sub(1);
+        } else if depth == 0 && ends_sentence(c, chars.get(i).copied()) {
+            while i < chars.len()
+                && (SENTENCE_ENDERS.contains(&chars[i])
+                    || is_closing(chars[i])
+                    || chars[i].is_whitespace())
+            {
+                current.push(chars[i]);
+                i += 1;
+            }
+            parts.push(current.trim().to_string());
+            current = String::new();
+        }
+    }
+    if current.chars().any(|c| c.is_alphanumeric()) {
+        parts.push(current.trim().to_string());
+    } else if let Some(last) = parts.last_mut() {
+        last.push_str(current.trim());
+    } else {
+        parts.push(current.trim().to_string());
+    }
+    parts
+}
+
+/// Chars of `text` outside its ASS override tags (`{\k20}`…), which karaoke lines keep.
+fn untagged(text: &str) -> impl Iterator<Item = char> + '_ {
+    let mut in_tag = false;
+    text.chars().filter(move |c| match c {
+        '{' => {
+            in_tag = true;
+            false
+        }
+        '}' if in_tag => {
+            in_tag = false;
+            false
+        }
+        _ => !in_tag,
+    })
+}
+
+fn weight(text: &str) -> u32 {
+    untagged(text).filter(|c| !c.is_whitespace()).count().max(1) as u32
+}
+
+/// Silences (start, end in ms) between `start` and `end` of the audiobook.
+fn detect_silences(audiobook: &Path, start: u32, end: u32) -> Vec<(u32, u32)> {
+    let output = ffmpeg_command()
+        .args([
+            "-hide_banner",
+            "-nostats",
+            "-ss",
+            &format!("{}.{:03}", start / 1000, start % 1000),
+            "-to",
+            &format!("{}.{:03}", end / 1000, end % 1000),
+            "-i",
+            &audiobook.to_string_lossy(),
+            "-af",
+            "silencedetect=noise=-35dB:d=0.12",
+            "-f",
+            "null",
+            "-",
+        ])
+        .output();
+    let Ok(output) = output else {
+        return vec![];
+    };
+    let parse = |line: &str, key: &str| -> Option<u32> {
+        let value = line.split(key).nth(1)?.split_whitespace().next()?;
+        Some(start + (value.parse::<f64>().ok()?.max(0.0) * 1000.0) as u32)
+    };
+    let mut silences = vec![];
+    let mut silence_start = None;
+    for line in String::from_utf8_lossy(&output.stderr).lines() {
+        if let Some(s) = parse(line, "silence_start: ") {
+            silence_start = Some(s);
+        } else if let Some(e) = parse(line, "silence_end: ") {
+            silences.push((silence_start.take().unwrap_or(start), e));
+        }
+    }
+    silences
+}
+
+/// Moves each split point to the middle of the nearest silence, when there is one close enough
+/// that doesn't reorder the parts.
+fn snap_to_silences(points: &mut [u32], silences: &[(u32, u32)], start: u32, end: u32) {
+    let mut previous = start;
+    for i in 0..points.len() {
+        let next = points.get(i + 1).copied().unwrap_or(end);
+        let point = points[i];
+        let best = silences
+            .iter()
+            .map(|(s, e)| (s + e) / 2)
+            .filter(|m| *m > previous && *m < next)
+            .min_by_key(|m| m.abs_diff(point))
+            .filter(|m| m.abs_diff(point) <= SILENCE_SNAP_WINDOW);
+        if let Some(m) = best {
+            points[i] = m;
+        }
+        previous = points[i];
+    }
+}
+
+/// Splits multi-sentence cues into one cue per sentence, sharing the cue's time range between
+/// the sentences by character count. When `audiobook` is given, the split points are refined by
+/// looking for silences around them.
+pub fn split_sentences(subs: Vec<Subtitle>, audiobook: Option<&Path>) -> Vec<Subtitle> {
+    let mut splits: Vec<(Subtitle, Vec<String>, Vec<u32>)> = subs
+        .into_iter()
+        .map(|s| {
+            let parts = sentence_parts(&s.text);
+            let start = millis(s.start_time);
+            let duration = millis(s.end_time).saturating_sub(start);
+            let total: u32 = parts.iter().map(|p| weight(p)).sum();
+            let mut elapsed = 0;
+            let points = parts[..parts.len() - 1]
+                .iter()
+                .map(|p| {
+                    elapsed += weight(p);
+                    start + (u64::from(duration) * u64::from(elapsed) / u64::from(total)) as u32
+                })
+                .collect();
+            (s, parts, points)
+        })
+        .collect();
+
+    if let Some(audiobook) = audiobook {
+        splits
+            .par_iter_mut()
+            .filter(|(_, _, points)| !points.is_empty())
+            .for_each(|(s, _, points)| {
+                let (start, end) = (millis(s.start_time), millis(s.end_time));
+                let silences = detect_silences(audiobook, start, end);
+                snap_to_silences(points, &silences, start, end);
+            });
+    }
+
+    let mut result = Vec::with_capacity(splits.len());
+    for (s, parts, points) in splits {
+        let mut start = s.start_time;
+        for (i, part) in parts.into_iter().enumerate() {
+            let mut sub = s.clone();
+            sub.num = result.len() + 1;
+            sub.text = part;
+            sub.start_time = start;
+            if let Some(point) = points.get(i) {
+                sub.end_time = Timestamp::from_milliseconds(*point);
+                start = sub.end_time;
+            }
+            result.push(sub);
+        }
+    }
+    result
+}
+
+/// When a cue is considered a fragment that should be joined to a neighbour.
+#[derive(Debug, Clone, Copy)]
+pub struct MergeThresholds {
+    /// Cues shorter than this, in milliseconds.
+    pub min_duration: u32,
+    /// Cues with fewer letters than this, punctuation not included.
+    pub min_chars: usize,
+    /// Move stray brackets (a `」` opening a cue, a `「` closing one) to the cue they belong to.
+    pub unbalanced_brackets: bool,
+}
+
+impl Default for MergeThresholds {
+    fn default() -> Self {
+        Self {
+            min_duration: 700,
+            min_chars: 3,
+            unbalanced_brackets: true,
+        }
+    }
+}
+
+fn content_len(text: &str) -> usize {
+    untagged(text).filter(|c| c.is_alphanumeric()).count()
+}
+
+fn join_text(a: &str, b: &str) -> String {
+    let spaced = a.chars().last().is_some_and(|c| c.is_ascii_alphanumeric())
+        && b.chars().next().is_some_and(|c| c.is_ascii_alphanumeric());
+    if spaced {
+        format!("{a} {b}")
+    } else {
+        format!("{a}{b}")
+    }
+}
+
+fn join(mut a: Subtitle, b: Subtitle) -> Subtitle {
+    a.text = join_text(a.text.trim_end(), b.text.trim_start());
+    a.start_time = a.start_time.min(b.start_time);
+    a.end_time = a.end_time.max(b.end_time);
+    a
+}
+
+/// Splits off the closing brackets at the start of `text` that have no opening bracket before
+/// them, and the opening brackets at its end that are never closed.
+fn stray_brackets(text: &str) -> (String, String, String) {
+    let trimmed = text.trim();
+    let leading: String = trimmed
+        .chars()
+        .take_while(|c| CLOSING_BRACKETS.contains(c))
+        .collect();
+    let rest = &trimmed[leading.len()..];
+    let trailing_len: usize = rest
+        .chars()
+        .rev()
+        .take_while(|c| OPENING_BRACKETS.contains(c))
+        .map(char::len_utf8)
+        .sum();
+    let (middle, trailing) = rest.split_at(rest.len() - trailing_len);
+    (leading, middle.to_string(), trailing.to_string())
+}
+
+/// Joins fragment cues (lone `「はい」`s, a trailing `」`) to a neighbour so they don't each
+/// get their own click and clip. Short cues go to whichever neighbour is closest in time.
+pub fn merge_fragments(subs: Vec<Subtitle>, thresholds: &MergeThresholds) -> Vec<Subtitle> {
+    let mut subs = subs;
+    if thresholds.unbalanced_brackets {
+        for i in 0..subs.len() {
+            let (leading, middle, trailing) = stray_brackets(&subs[i].text);
+            let mut kept = String::new();
+            if !leading.is_empty() && i > 0 {
+                subs[i - 1].text.push_str(&leading);
+            } else {
+                kept.push_str(&leading);
+            }
+            kept.push_str(&middle);
+            if !trailing.is_empty() && i + 1 < subs.len() {
+                subs[i + 1].text.insert_str(0, &trailing);
+            } else {
+                kept.push_str(&trailing);
+            }
+            subs[i].text = kept;
+        }
+    }
+
+    let millis_between = |a: Timestamp, b: Timestamp| millis(b).saturating_sub(millis(a));
+    let is_fragment = |s: &Subtitle| {
+        content_len(&s.text) < thresholds.min_chars
+            || millis_between(s.start_time, s.end_time) < thresholds.min_duration
+    };
+
+    let mut result: Vec<Subtitle> = Vec::with_capacity(subs.len());
+    let mut carry: Option<Subtitle> = None;
+    for i in 0..subs.len() {
+        let mut s = subs[i].clone();
+        if let Some(c) = carry.take() {
+            s = join(c, s);
+        }
+        if !is_fragment(&s) {
+            result.push(s);
+            continue;
+        }
+        let gap_prev = result
+            .last()
+            .map(|p| millis_between(p.end_time, s.start_time));
+        let gap_next = subs
+            .get(i + 1)
+            .map(|n| millis_between(s.end_time, n.start_time));
+        match (gap_prev, gap_next) {
+            (Some(p), Some(n)) if n < p => carry = Some(s),
+            (None, Some(_)) => carry = Some(s),
+            (Some(_), _) => {
+                let previous = result.pop().unwrap();
+                result.push(join(previous, s));
+            }
+            (None, None) => result.push(s),
+        }
+    }
+    for (i, s) in result.iter_mut().enumerate() {
+        s.num = i + 1;
+    }
+    result
+}
@@ -0,0 +1,262 @@
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::ops::Range;
+use std::path::Path;
+
+use super::markup::{self, Markup, Span};
+
+/// Reading column of IPADIC rows (`読み`).
+const IPADIC_READING: usize = 11;
+/// Reading column of UniDic rows (`kana`), which have many more columns than IPADIC ones.
+const UNIDIC_READING: usize = 24;
+/// Cost of a char the dictionary doesn't know, high enough for any word to be preferred.
+const UNKNOWN_COST: i32 = 10_000;
+
+/// How often a word gets its furigana.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FuriganaRepeat {
+    Always,
+    /// Only the first time a word appears in each chapter.
+    FirstPerChapter,
+    /// Only words with a kanji missing from the known kanji list.
+    UnknownKanji,
+}
+
+/// A morphological dictionary (IPADIC or UniDic CSV rows), used to segment lines into words and
+/// read them.
+pub struct Dictionary {
+    /// Surface form to (reading in hiragana, cost).
+    words: HashMap<String, (String, i32)>,
+    /// Longest word starting with each char, in chars.
+    max_len: HashMap<char, usize>,
+}
+
+pub fn is_kanji(c: char) -> bool {
+    matches!(c, '\u{4E00}'..='\u{9FFF}' | '\u{3400}'..='\u{4DBF}' | '\u{F900}'..='\u{FAFF}' | '々' | '〆')
+}
+
+pub fn is_kana(c: char) -> bool {
+    matches!(c, '\u{3041}'..='\u{309F}' | '\u{30A0}'..='\u{30FF}')
+}
+
+pub fn to_hiragana(text: &str) -> String {
+    text.chars()
+        .map(|c| match c {
+            '\u{30A1}'..='\u{30F6}' => char::from_u32(c as u32 - 0x60).unwrap_or(c),
+            c => c,
+        })
+        .collect()
+}
+
+/// Splits a CSV row, honouring double quoted fields.
+fn csv_fields(line: &str) -> Vec<String> {
+    let mut fields = vec![];
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => fields.push(std::mem::take(&mut field)),
+            c => field.push(c),
+        }
+    }
+    fields.push(field);
+    fields
+}
+
+/// Strips the kana the word and its reading share at both ends (`食べる`/`たべる` gives
+/// `食`/`た`), returning the char range of the word left and its reading.
+fn strip_okurigana(surface: &str, reading: &str) -> Option<(Range<usize>, String)> {
+    let surface: Vec<char> = surface.chars().collect();
+    let reading: Vec<char> = to_hiragana(reading).chars().collect();
+    let same = |s: char, r: char| is_kana(s) && to_hiragana(&s.to_string()).starts_with(r);
+    let mut start = 0;
+    while start < surface.len() && start < reading.len() && same(surface[start], reading[start]) {
+        start += 1;
+    }
+    let mut end = 0;
+    while end < surface.len() - start
+        && end < reading.len() - start
+        && same(
+            surface[surface.len() - 1 - end],
+            reading[reading.len() - 1 - end],
+        )
+    {
+        end += 1;
+    }
+    let range = start..surface.len() - end;
+    let core_reading: String = reading[start..reading.len() - end].iter().collect();
+    if range.is_empty()
+        || core_reading.is_empty()
+        || !surface[range.clone()].iter().any(|c| is_kanji(*c))
+    {
+        return None;
+    }
+    Some((range, core_reading))
+}
+
+impl Dictionary {
+    fn read_file(&mut self, path: &Path) -> io::Result<()> {
+        let bytes = std::fs::read(path)?;
+        let text = match String::from_utf8(bytes) {
+            Ok(text) => text,
+            // IPADIC is distributed in EUC-JP.
+            Err(err) => encoding_rs::EUC_JP.decode(err.as_bytes()).0.into_owned(),
+        };
+        for line in text.lines() {
+            let fields = csv_fields(line);
+            let column = if fields.len() > UNIDIC_READING {
+                UNIDIC_READING
+            } else {
+                IPADIC_READING
+            };
+            let (Some(surface), Some(cost), Some(reading)) =
+                (fields.first(), fields.get(3), fields.get(column))
+            else {
+                continue;
+            };
+            let Ok(cost) = cost.parse::<i32>() else {
+                continue;
+            };
+            if surface.is_empty() || reading.is_empty() || reading == "*" {
+                continue;
+            }
+            let first = surface.chars().next().unwrap();
+            let len = surface.chars().count();
+            let max_len = self.max_len.entry(first).or_insert(0);
+            *max_len = (*max_len).max(len);
+            let entry = self
+                .words
+                .entry(surface.to_string())
+                .or_insert((to_hiragana(reading), cost));
+            if cost < entry.1 {
+                *entry = (to_hiragana(reading), cost);
+            }
+        }
+        Ok(())
+    }
+
+    /// Loads a dictionary CSV, or every CSV of a folder (IPADIC comes as one file per part of
+    /// speech). Fails when there isn't a single word in it.
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let mut dictionary = Self {
+            words: HashMap::new(),
+            max_len: HashMap::new(),
+        };
+        if path.is_dir() {
+            for entry in std::fs::read_dir(path)? {
+                let entry = entry?.path();
+                if entry.extension().is_some_and(|e| e == "csv") {
+                    dictionary.read_file(&entry)?;
+                }
+            }
+        } else {
+            dictionary.read_file(path)?;
+        }
+        if dictionary.words.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "no IPADIC or UniDic rows in it",
+            ));
+        }
+        Ok(dictionary)
+    }
+
+    /// Cheapest split of `text` into dictionary words, as char ranges with their reading (none
+    /// for chars the dictionary doesn't know). Only the words' own costs count: the connection
+    /// costs of `matrix.def` aren't read, so a split the grammar rules out can still win.
+    pub fn segment(&self, text: &str) -> Vec<(Range<usize>, Option<&str>)> {
+        let offsets: Vec<usize> = text
+            .char_indices()
+            .map(|(i, _)| i)
+            .chain(std::iter::once(text.len()))
+            .collect();
+        let chars: Vec<char> = text.chars().collect();
+        let n = chars.len();
+        // Cheapest cost to reach each position, and the word that got there.
+        let mut best: Vec<(i32, usize, Option<&str>)> = vec![(i32::MAX, 0, None); n + 1];
+        best[0].0 = 0;
+        for i in 0..n {
+            if best[i].0 == i32::MAX {
+                continue;
+            }
+            let base = best[i].0;
+            if base + UNKNOWN_COST < best[i + 1].0 {
+                best[i + 1] = (base + UNKNOWN_COST, i, None);
+            }
+            let max_len = self.max_len.get(&chars[i]).copied().unwrap_or(0);
+            for len in 1..=max_len.min(n - i) {
+                if let Some((reading, cost)) = self.words.get(&text[offsets[i]..offsets[i + len]]) {
+                    let total = base.saturating_add(*cost);
+                    if total < best[i + len].0 {
+                        best[i + len] = (total, i, Some(reading.as_str()));
+                    }
+                }
+            }
+        }
+        let mut words = vec![];
+        let mut end = n;
+        while end > 0 {
+            let (_, start, reading) = best[end];
+            words.push((start..end, reading));
+            end = start;
+        }
+        words.reverse();
+        words
+    }
+}
+
+/// Kanji of a known kanji list file, which can be any text: every kanji in it counts as known.
+pub fn load_known_kanji(path: &Path) -> io::Result<HashSet<char>> {
+    Ok(std::fs::read_to_string(path)?
+        .chars()
+        .filter(|c| is_kanji(*c))
+        .collect())
+}
+
+/// Adds furigana to the kanji words of every line, where nothing else annotates them already.
+pub fn add_furigana(
+    dictionary: &Dictionary,
+    lines: &[String],
+    chapters: &[usize],
+    repeat: FuriganaRepeat,
+    known_kanji: &HashSet<char>,
+    spans: &mut [Vec<Span>],
+) {
+    let mut seen: HashSet<(usize, String)> = HashSet::new();
+    for (i, line) in lines.iter().enumerate() {
+        let chars: Vec<char> = line.chars().collect();
+        for (range, reading) in dictionary.segment(line) {
+            let Some(reading) = reading else {
+                continue;
+            };
+            let surface: String = chars[range.clone()].iter().collect();
+            let Some((core, core_reading)) = strip_okurigana(&surface, reading) else {
+                continue;
+            };
+            let wanted = match repeat {
+                FuriganaRepeat::Always => true,
+                FuriganaRepeat::FirstPerChapter => {
+                    seen.insert((chapters.get(i).copied().unwrap_or(0), surface.clone()))
+                }
+                FuriganaRepeat::UnknownKanji => surface
+                    .chars()
+                    .any(|c| is_kanji(c) && !known_kanji.contains(&c)),
+            };
+            if wanted {
+                markup::add_span(
+                    &mut spans[i],
+                    Span {
+                        range: range.start + core.start..range.start + core.end,
+                        markup: Markup::Ruby(core_reading),
+                    },
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,735 @@
+use epub::doc::{EpubDoc, NavPoint};
+use regex::Regex;
+use scraper::{node::Node, ElementRef, Html};
+use std::collections::HashMap;
+use std::io::{Read, Seek};
+use std::ops::Range;
+use std::path::{Component, Path, PathBuf};
+
+use super::gaiji::GAIJI_MAX_SIZE;
+
+/// Elements whose text is the paragraph a ruby gets matched against.
+const BLOCK_ELEMENTS: [&str; 12] = [
+    "p",
+    "div",
+    "li",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "td",
+    "blockquote",
+    "body",
+];
+/// Ruby annotations, left out of the text they annotate.
+const ANNOTATION_ELEMENTS: [&str; 3] = ["rt", "rp", "rtc"];
+/// Elements that aren't part of the text flow at all.
+const SKIPPED_ELEMENTS: [&str; 5] = ["head", "script", "style", "title", "template"];
+/// `epub:type`s (and `doc-` roles) of the notes, which are left out of the text.
+const NOTE_TYPES: [&str; 4] = ["footnote", "endnote", "rearnote", "note"];
+/// `epub:type`s (and `doc-` roles) of the lists of notes, also left out of the text.
+const NOTE_LIST_TYPES: [&str; 3] = ["footnotes", "endnotes", "rearnotes"];
+
+/// Classes publishers commonly use for 傍点, taken as emphasis dots unless the user maps them.
+const EMPHASIS_CLASSES: [&str; 8] = [
+    "em-sesame",
+    "em-sesame-open",
+    "em-dot",
+    "em-dot-open",
+    "em-circle",
+    "bouten",
+    "boten",
+    "sesame_dot",
+];
+
+/// How a stretch of the book's text is set.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TextStyle {
+    /// 傍点, shown as dots over each char.
+    Emphasis,
+    Bold,
+    Italic,
+}
+
+impl TextStyle {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.trim().to_lowercase().as_str() {
+            "emphasis" | "dots" | "em" => Some(Self::Emphasis),
+            "bold" | "b" => Some(Self::Bold),
+            "italic" | "i" => Some(Self::Italic),
+            _ => None,
+        }
+    }
+
+    /// Style a CSS declaration block gives its text, if it's one we carry over.
+    fn from_css(declarations: &str) -> Option<Self> {
+        let declarations: Vec<(String, String)> = declarations
+            .split(';')
+            .filter_map(|declaration| {
+                let (property, value) = declaration.split_once(':')?;
+                let value = value.to_lowercase().replace("!important", "");
+                Some((property.trim().to_lowercase(), value.trim().to_string()))
+            })
+            .collect();
+        let has =
+            |style: &dyn Fn(&str, &str) -> bool| declarations.iter().any(|(p, v)| style(p, v));
+        // The shorthand or the style, prefixed or not, but not `text-emphasis-position` or
+        // `-color` alone, which show nothing.
+        let emphasis = |property: &str, value: &str| {
+            let property = property
+                .trim_start_matches("-webkit-")
+                .trim_start_matches("-epub-");
+            matches!(property, "text-emphasis" | "text-emphasis-style")
+                && !value.is_empty()
+                && value != "none"
+        };
+        let bold = |property: &str, value: &str| {
+            property == "font-weight" && matches!(value, "bold" | "bolder" | "700" | "800" | "900")
+        };
+        let italic = |property: &str, value: &str| {
+            property == "font-style" && matches!(value, "italic" | "oblique")
+        };
+        if has(&emphasis) {
+            Some(Self::Emphasis)
+        } else if has(&bold) {
+            Some(Self::Bold)
+        } else if has(&italic) {
+            Some(Self::Italic)
+        } else {
+            None
+        }
+    }
+}
+
+/// Parses a `class=style` table, comma or line separated (`em-sesame=emphasis, gothic=bold`),
+/// ignoring entries it can't read.
+pub fn parse_style_classes(table: &str) -> HashMap<String, TextStyle> {
+    table
+        .split([',', '\n'])
+        .filter_map(|entry| {
+            let (class, style) = entry.split_once('=')?;
+            Some((class.trim().to_string(), TextStyle::from_name(style)?))
+        })
+        .filter(|(class, _)| !class.is_empty())
+        .collect()
+}
+
+/// A styled stretch of [`EpubText::text`], in chars.
+#[derive(Debug, Clone)]
+pub struct StyledSpan {
+    pub style: TextStyle,
+    pub span: Range<usize>,
+}
+
+/// An image of the book, where it is in the text.
+#[derive(Debug, Clone)]
+pub struct BookImage {
+    /// Path of the image inside the epub, as in its resources.
+    pub path: PathBuf,
+    /// Manifest id of the image, `None` when the path isn't in the manifest.
+    pub id: Option<String>,
+    /// Char of [`EpubText::text`] the image comes right before.
+    pub position: usize,
+    /// Spine item (chapter document) the image is in.
+    pub spine: usize,
+    /// Whether the markup says it stands for a character (a `gaiji` class or name, or the
+    /// size of a char).
+    pub gaiji: bool,
+}
+
+/// A footnote or endnote of the book.
+#[derive(Debug, Clone)]
+pub struct BookNote {
+    /// Path of its document inside the epub and its id, as `path#id`.
+    pub target: String,
+    pub text: String,
+}
+
+/// A link to a note, and where it is in the text.
+#[derive(Debug, Clone)]
+pub struct NoteRef {
+    /// The [`BookNote::target`] it points to.
+    pub target: String,
+    /// What the link shows, usually a number or an asterisk.
+    pub marker: String,
+    /// Char of [`EpubText::text`] the link comes right before.
+    pub position: usize,
+    /// Spine item the link is in.
+    pub spine: usize,
+}
+
+/// Side of a spread a page goes on, from the `page-spread-*` properties of its itemref.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PageSpread {
+    Left,
+    Right,
+    /// Both sides at once, a page shown on its own.
+    Center,
+}
+
+impl PageSpread {
+    fn from_properties(properties: &str) -> Option<Self> {
+        properties.split_whitespace().find_map(|p| {
+            match p.strip_prefix("rendition:").unwrap_or(p) {
+                "page-spread-left" => Some(Self::Left),
+                "page-spread-right" => Some(Self::Right),
+                "page-spread-center" => Some(Self::Center),
+                _ => None,
+            }
+        })
+    }
+}
+
+/// `properties` of each itemref of the package's spine, by idref.
+fn itemref_properties(opf: &str) -> HashMap<String, String> {
+    let itemref = Regex::new(r"<itemref\b[^>]*>").unwrap();
+    let idref = Regex::new(r#"\bidref\s*=\s*["']([^"']*)["']"#).unwrap();
+    let properties = Regex::new(r#"\bproperties\s*=\s*["']([^"']*)["']"#).unwrap();
+    itemref
+        .find_iter(opf)
+        .filter_map(|tag| {
+            let id = idref.captures(tag.as_str())?[1].to_string();
+            let properties = properties.captures(tag.as_str())?[1].to_string();
+            Some((id, properties))
+        })
+        .collect()
+}
+
+/// The `epub:type`s and `doc-` roles of an element.
+fn semantic_types<'a>(element: &ElementRef<'a>) -> impl Iterator<Item = &'a str> {
+    let types = element.value().attr("epub:type").unwrap_or_default();
+    let roles = element.value().attr("role").unwrap_or_default();
+    types.split_whitespace().chain(
+        roles
+            .split_whitespace()
+            .map(|r| r.trim_start_matches("doc-")),
+    )
+}
+
+/// `text` with its `%XX` escapes decoded, the bytes they make being read as UTF-8.
+fn percent_decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escape = bytes
+            .get(i + 1..i + 3)
+            .filter(|hex| bytes[i] == b'%' && hex.iter().all(u8::is_ascii_hexdigit))
+            .and_then(|hex| u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok());
+        match escape {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// Path inside the epub of what `href` points to, from the document at `document`.
+pub fn resolve_href(document: &Path, href: &str) -> PathBuf {
+    let href = href.split('#').next().unwrap_or_default();
+    let href = percent_decode(href);
+    let mut path = PathBuf::new();
+    let joined = document.parent().unwrap_or(Path::new("")).join(href);
+    for component in joined.components() {
+        match component {
+            Component::ParentDir => {
+                path.pop();
+            }
+            Component::CurDir => {}
+            c => path.push(c),
+        }
+    }
+    path
+}
+
+/// Title of every document the table of contents points to, the first one when it points to
+/// several places of it.
+fn toc_titles(points: &[NavPoint], titles: &mut HashMap<PathBuf, String>) {
+    for point in points {
+        let path = resolve_href(Path::new(""), &point.content.to_string_lossy());
+        let label = point.label.trim();
+        if !label.is_empty() {
+            titles.entry(path).or_insert_with(|| label.to_string());
+        }
+        toc_titles(&point.children, titles);
+    }
+}
+
+/// Whether an `<img>`/`<image>` element is a gaiji rather than an illustration, from its markup.
+pub fn is_gaiji(element: ElementRef, src: &str) -> bool {
+    let value = element.value();
+    let small = |attr: &str| {
+        value
+            .attr(attr)
+            .and_then(|v| v.trim_end_matches("px").parse::<u32>().ok())
+            .is_some_and(|v| v <= GAIJI_MAX_SIZE)
+    };
+    value.classes().any(|c| c.to_lowercase().contains("gaiji"))
+        || src.to_lowercase().contains("gaiji")
+        || small("width")
+        || small("height")
+}
+
+/// One annotated span of a `<ruby>`: a single kanji for mono-ruby, the whole word for group ruby.
+#[derive(Debug, Clone)]
+pub struct Ruby {
+    pub base: String,
+    pub reading: String,
+    /// Text of the paragraph the ruby is in, readings left out.
+    pub context: String,
+    /// Chars of [`EpubText::text`] the base covers.
+    pub span: Range<usize>,
+}
+
+/// The whole book as plain text, one paragraph per line, with the rubies placed on it.
+#[derive(Debug, Default)]
+pub struct EpubText {
+    pub text: String,
+    pub rubies: Vec<Ruby>,
+    pub styles: Vec<StyledSpan>,
+    pub images: Vec<BookImage>,
+    /// Char of `text` each spine item starts at.
+    pub spine_starts: Vec<usize>,
+    /// Title of the chapter each spine item is part of, from the table of contents.
+    pub chapter_titles: Vec<Option<String>>,
+    /// Whether each spine item is a whole page, being of a fixed layout or nothing but images.
+    pub pages: Vec<bool>,
+    /// Side of a spread each spine item goes on, when the spine says.
+    pub spreads: Vec<Option<PageSpread>>,
+    /// Footnotes and endnotes, left out of `text`.
+    pub notes: Vec<BookNote>,
+    pub note_refs: Vec<NoteRef>,
+    /// Documents of the spine that couldn't be read, left out of the text.
+    pub unreadable: Vec<PathBuf>,
+    /// Whether the pages go from right to left, from the spine's `page-progression-direction`.
+    pub right_to_left: bool,
+    /// Path inside the epub of the document being read, to resolve image paths against.
+    document: PathBuf,
+    /// Style of each class, from the book's stylesheets and the user's table.
+    classes: HashMap<String, TextStyle>,
+    /// Length of `text` in chars.
+    len: usize,
+    /// Text of the block elements being read, innermost last, so rubies don't each collect
+    /// their paragraph again.
+    contexts: Vec<String>,
+}
+
+/// Text of an element without any ruby annotation in it.
+pub fn plain_text(element: ElementRef) -> String {
+    let mut text = String::new();
+    for child in element.children() {
+        match child.value() {
+            Node::Text(t) => text.push_str(t),
+            Node::Element(e) if !ANNOTATION_ELEMENTS.contains(&e.name()) => {
+                if let Some(child) = ElementRef::wrap(child) {
+                    text.push_str(&plain_text(child));
+                }
+            }
+            _ => {}
+        }
+    }
+    text
+}
+
+/// Text of an `<rt>`/`<rtc>`, without the `<rp>` fallback parentheses.
+fn annotation_text(element: ElementRef) -> String {
+    let mut text = String::new();
+    for child in element.children() {
+        match child.value() {
+            Node::Text(t) => text.push_str(t),
+            Node::Element(e) if e.name() != "rp" => {
+                if let Some(child) = ElementRef::wrap(child) {
+                    text.push_str(&annotation_text(child));
+                }
+            }
+            _ => {}
+        }
+    }
+    text.trim().to_string()
+}
+
+/// Pairs up the bases and readings of one base/annotation segment of a ruby.
+fn close_segment(
+    bases: &mut Vec<String>,
+    readings: &mut Vec<String>,
+    complement: &mut Option<String>,
+    pairs: &mut Vec<(String, String)>,
+) {
+    bases.retain(|b| !b.is_empty());
+    readings.retain(|r| !r.is_empty());
+    if readings.is_empty() {
+        if let Some(c) = complement.take() {
+            readings.push(c);
+        }
+    }
+    if !bases.is_empty() && !readings.is_empty() {
+        if bases.len() == readings.len() {
+            pairs.extend(bases.drain(..).zip(readings.drain(..)));
+        } else {
+            pairs.push((bases.concat(), readings.concat()));
+        }
+    }
+    bases.clear();
+    readings.clear();
+    *complement = None;
+}
+
+/// Base/reading pairs of a `<ruby>` element. Handles `<rb>`-less markup, `<rp>` fallbacks,
+/// tabular `<rb>…<rt>…` mono-ruby, interleaved mono-ruby (`漢<rt>かん</rt>字<rt>じ</rt>`) and
+/// group ruby, using `<rtc>` only when there is no `<rt>` to use.
+pub fn ruby_pairs(ruby: ElementRef) -> Vec<(String, String)> {
+    let mut pairs = vec![];
+    let mut bases: Vec<String> = vec![];
+    let mut readings: Vec<String> = vec![];
+    let mut complement: Option<String> = None;
+    // Loose text and inline elements between two `<rb>`/`<rt>` form a single base.
+    let mut loose = String::new();
+
+    for child in ruby.children() {
+        let (name, element) = match child.value() {
+            Node::Text(t) => {
+                if !readings.is_empty() && !t.trim().is_empty() {
+                    close_segment(&mut bases, &mut readings, &mut complement, &mut pairs);
+                }
+                loose.push_str(t.trim());
+                continue;
+            }
+            Node::Element(e) => match ElementRef::wrap(child) {
+                Some(element) => (e.name(), element),
+                None => continue,
+            },
+            _ => continue,
+        };
+        match name {
+            "rt" | "rtc" => {
+                bases.push(std::mem::take(&mut loose));
+                if name == "rt" {
+                    readings.push(annotation_text(element));
+                } else {
+                    complement = Some(annotation_text(element));
+                }
+            }
+            "rp" => {}
+            _ => {
+                if !readings.is_empty() {
+                    close_segment(&mut bases, &mut readings, &mut complement, &mut pairs);
+                }
+                if name == "rb" {
+                    bases.push(std::mem::take(&mut loose));
+                    bases.push(plain_text(element).trim().to_string());
+                } else {
+                    loose.push_str(plain_text(element).trim());
+                }
+            }
+        }
+    }
+    bases.push(loose);
+    close_segment(&mut bases, &mut readings, &mut complement, &mut pairs);
+    pairs
+}
+
+fn collapse_whitespace(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+impl EpubText {
+    /// Reads every document of the spine, in reading order. `classes` maps class names to the
+    /// style they stand for, over what the book's stylesheets say.
+    pub fn from_epub<R: Read + Seek>(
+        doc: &mut EpubDoc<R>,
+        classes: &HashMap<String, TextStyle>,
+    ) -> Self {
+        let mut text = Self::default();
+        for class in EMPHASIS_CLASSES {
+            text.classes.insert(class.to_string(), TextStyle::Emphasis);
+        }
+        let stylesheets: Vec<String> = doc
+            .resources
+            .iter()
+            .filter(|(_, (_, mime))| mime == "text/css")
+            .map(|(id, _)| id.clone())
+            .collect();
+        for id in stylesheets {
+            if let Some((css, _)) = doc.get_resource_str(&id) {
+                text.read_stylesheet(&css);
+            }
+        }
+        text.classes
+            .extend(classes.iter().map(|(c, s)| (c.clone(), *s)));
+        let mut titles = HashMap::new();
+        toc_titles(&doc.toc, &mut titles);
+        let fixed_layout = doc
+            .mdata("rendition:layout")
+            .is_some_and(|l| l.trim() == "pre-paginated");
+        let package = doc.root_file.clone();
+        let opf = doc.get_resource_str_by_path(package).unwrap_or_default();
+        text.right_to_left =
+            Regex::new(r#"<spine[^>]*page-progression-direction\s*=\s*["']rtl["']"#)
+                .unwrap()
+                .is_match(&opf);
+        let itemrefs = itemref_properties(&opf);
+        doc.set_current_page(0);
+        loop {
+            text.document = doc.get_current_path().unwrap_or_default();
+            text.spine_starts.push(text.len);
+            // Items the table of contents skips are part of the chapter before them.
+            let title = match titles.get(&text.document) {
+                Some(title) => Some(title.clone()),
+                None => text.chapter_titles.last().cloned().flatten(),
+            };
+            text.chapter_titles.push(title);
+            let images = text.images.len();
+            let start = text.text.len();
+            match doc.get_current_str() {
+                Some((v, _)) => text.push_html(&v),
+                None => text.unreadable.push(text.document.clone()),
+            }
+            let properties = doc
+                .get_current_id()
+                .and_then(|id| itemrefs.get(&id))
+                .map_or("", |p| p.as_str());
+            let fixed = match properties
+                .split_whitespace()
+                .find_map(|p| p.strip_prefix("rendition:layout-"))
+            {
+                Some(layout) => layout == "pre-paginated",
+                None => fixed_layout,
+            };
+            let blank = text.text[start..].trim().is_empty();
+            text.pages
+                .push((fixed || blank) && text.images.len() > images);
+            text.spreads.push(PageSpread::from_properties(properties));
+            if !doc.go_next() {
+                break;
+            }
+        }
+        let ids: HashMap<&PathBuf, &String> = doc
+            .resources
+            .iter()
+            .map(|(id, (path, _))| (path, id))
+            .collect();
+        for image in &mut text.images {
+            image.id = ids.get(&image.path).map(|id| id.to_string());
+        }
+        text
+    }
+
+    /// Learns which classes the stylesheet sets in emphasis dots, bold or italics.
+    pub fn read_stylesheet(&mut self, css: &str) {
+        let comments = Regex::new(r"(?s)/\*.*?\*/").unwrap();
+        let rules = Regex::new(r"([^{}]+)\{([^}]*)\}").unwrap();
+        let class = Regex::new(r"\.([\w-]+)\s*$").unwrap();
+        let css = comments.replace_all(css, "");
+        for rule in rules.captures_iter(&css) {
+            let Some(style) = TextStyle::from_css(&rule[2]) else {
+                continue;
+            };
+            // Only rules ending on a plain class, `.em-sesame` or `span.bouten`.
+            for selector in rule[1].split(',') {
+                if let Some(name) = class.captures(selector.trim()) {
+                    self.classes.insert(name[1].to_string(), style);
+                }
+            }
+        }
+    }
+
+    /// Styles an element sets its text in, from its name, classes and inline style.
+    fn element_styles(&self, element: ElementRef) -> Vec<TextStyle> {
+        let value = element.value();
+        let mut styles: Vec<TextStyle> = value
+            .classes()
+            .filter_map(|c| self.classes.get(c).copied())
+            .collect();
+        if let Some(style) = value.attr("style").and_then(TextStyle::from_css) {
+            styles.push(style);
+        }
+        if styles.is_empty() {
+            match value.name() {
+                "b" | "strong" => styles.push(TextStyle::Bold),
+                "i" | "em" => styles.push(TextStyle::Italic),
+                _ => {}
+            }
+        }
+        styles.dedup();
+        styles
+    }
+
+    /// Chars of `text` that spine item `spine` takes up.
+    pub fn spine_range(&self, spine: usize) -> Range<usize> {
+        let start = self.spine_starts.get(spine).copied().unwrap_or(0);
+        let end = self
+            .spine_starts
+            .get(spine + 1)
+            .copied()
+            .unwrap_or(self.len);
+        start..end
+    }
+
+    fn push(&mut self, text: &str) {
+        let text = if self.text.is_empty() || self.text.ends_with(['\n', ' ']) {
+            text.trim_start()
+        } else {
+            text
+        };
+        self.len += text.chars().count();
+        self.text.push_str(text);
+    }
+
+    fn new_line(&mut self) {
+        if !self.text.is_empty() && !self.text.ends_with('\n') {
+            if self.text.ends_with(' ') {
+                self.text.pop();
+                self.len -= 1;
+            }
+            self.text.push('\n');
+            self.len += 1;
+        }
+    }
+
+    fn push_ruby(&mut self, ruby: ElementRef) {
+        let context = self.contexts.last().cloned().unwrap_or_default();
+        let bases = collapse_whitespace(&plain_text(ruby));
+        let start = self.len;
+        self.push(&bases);
+
+        let mut cursor = 0;
+        for (base, reading) in ruby_pairs(ruby) {
+            let Some(offset) = bases[cursor..].find(&base) else {
+                continue;
+            };
+            let base_start = start + bases[..cursor + offset].chars().count();
+            cursor += offset + base.len();
+            self.rubies.push(Ruby {
+                span: base_start..base_start + base.chars().count(),
+                base,
+                reading,
+                context: context.clone(),
+            });
+        }
+    }
+
+    fn push_image(&mut self, element: ElementRef) {
+        // `<img src>`, and SVG's `<image href>` or `<image xlink:href>`: the latter shows up
+        // under its local name.
+        let attribute = if element.value().name() == "img" {
+            "src"
+        } else {
+            "href"
+        };
+        let src = element
+            .value()
+            .attrs()
+            .find(|(name, _)| *name == attribute)
+            .map(|(_, value)| value.to_string());
+        if let Some(src) = src {
+            self.images.push(BookImage {
+                path: resolve_href(&self.document, &src),
+                id: None,
+                position: self.len,
+                spine: self.spine_starts.len().saturating_sub(1),
+                gaiji: is_gaiji(element, &src),
+            });
+        }
+    }
+
+    /// Reads the notes of a note or list of notes: the element itself when it has an id, the
+    /// outermost elements with one inside it otherwise, each being a whole note.
+    fn push_notes(&mut self, element: ElementRef) {
+        let list = semantic_types(&element).any(|t| NOTE_LIST_TYPES.contains(&t));
+        match element.value().id() {
+            Some(id) if !list => self.notes.push(BookNote {
+                target: format!("{}#{id}", self.document.display()),
+                text: collapse_whitespace(&plain_text(element)).trim().to_string(),
+            }),
+            _ => {
+                for child in element.children().filter_map(ElementRef::wrap) {
+                    self.push_notes(child);
+                }
+            }
+        }
+    }
+
+    fn push_note_ref(&mut self, element: ElementRef) {
+        let href = element.value().attr("href").unwrap_or_default();
+        let (path, fragment) = href.split_once('#').unwrap_or((href, ""));
+        let path = match path {
+            "" => self.document.clone(),
+            path => resolve_href(&self.document, path),
+        };
+        self.note_refs.push(NoteRef {
+            target: format!("{}#{fragment}", path.display()),
+            marker: collapse_whitespace(&plain_text(element)).trim().to_string(),
+            position: self.len,
+            spine: self.spine_starts.len().saturating_sub(1),
+        });
+    }
+
+    fn push_element(&mut self, element: ElementRef) {
+        let name = element.value().name();
+        if SKIPPED_ELEMENTS.contains(&name) || ANNOTATION_ELEMENTS.contains(&name) {
+            return;
+        }
+        if semantic_types(&element).any(|t| NOTE_TYPES.contains(&t) || NOTE_LIST_TYPES.contains(&t))
+        {
+            self.push_notes(element);
+            return;
+        }
+        if name == "a" && semantic_types(&element).any(|t| t == "noteref") {
+            self.push_note_ref(element);
+            return;
+        }
+        match name {
+            "ruby" => self.push_ruby(element),
+            "br" => self.new_line(),
+            "img" | "image" => self.push_image(element),
+            _ => {
+                let block = BLOCK_ELEMENTS.contains(&name);
+                let styles = self.element_styles(element);
+                if block {
+                    self.new_line();
+                    self.contexts
+                        .push(collapse_whitespace(&plain_text(element)));
+                }
+                let start = self.len;
+                for child in element.children() {
+                    match child.value() {
+                        Node::Text(t) => self.push(&t.replace(['\n', '\r', '\t'], " ")),
+                        Node::Element(_) => {
+                            if let Some(child) = ElementRef::wrap(child) {
+                                self.push_element(child);
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+                if self.len > start {
+                    for style in styles {
+                        self.styles.push(StyledSpan {
+                            style,
+                            span: start..self.len,
+                        });
+                    }
+                }
+                if block {
+                    self.new_line();
+                    self.contexts.pop();
+                }
+            }
+        }
+    }
+
+    /// Appends the text of one (X)HTML document.
+    pub fn push_html(&mut self, html: &str) {
+        let document = Html::parse_document(html);
+        self.push_element(document.root_element());
+        self.new_line();
+    }
+}
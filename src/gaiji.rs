@@ -0,0 +1,82 @@
+use epub::doc::EpubDoc;
+use std::collections::HashMap;
+use std::io::{Cursor, Read, Seek};
+use std::path::{Path, PathBuf};
+
+use super::epub_text::BookImage;
+use super::illustrations::image_name;
+
+/// Images up to this size (px) are taken as gaiji: by a width or height attribute in the markup,
+/// by both sides of the image itself when the markup doesn't say.
+pub const GAIJI_MAX_SIZE: u32 = 64;
+/// `gui.text_size` of a new Ren'Py project.
+const DEFAULT_TEXT_SIZE: u32 = 33;
+/// Where gaiji go, relative to `game/`.
+const GAIJI_FOLDER: &str = "images/gaiji";
+
+/// Text height of the game, from the `gui.text_size` define of `gui.rpy`.
+pub fn text_height(game_folder: &Path) -> u32 {
+    std::fs::read_to_string(game_folder.join("gui.rpy"))
+        .ok()
+        .and_then(|gui| {
+            gui.lines()
+                .filter_map(|l| l.trim().strip_prefix("define gui.text_size"))
+                .find_map(|l| l.trim().strip_prefix('=')?.trim().parse().ok())
+        })
+        .unwrap_or(DEFAULT_TEXT_SIZE)
+}
+
+fn dimensions(data: &[u8]) -> Option<(u32, u32)> {
+    image::ImageReader::new(Cursor::new(data))
+        .with_guessed_format()
+        .ok()?
+        .into_dimensions()
+        .ok()
+}
+
+/// Marks the book's images that are small enough to be gaiji, and writes every gaiji to
+/// `game/images/gaiji/` at the height of the text, transparency kept. Returns the Ren'Py path
+/// of each gaiji by its path in the epub.
+pub fn export_gaiji<R: Read + Seek>(
+    doc: &mut EpubDoc<R>,
+    images: &mut [BookImage],
+    game_folder: &Path,
+) -> HashMap<PathBuf, String> {
+    let height = text_height(game_folder);
+    let folder = game_folder.join(GAIJI_FOLDER);
+    let mut exported: HashMap<PathBuf, String> = HashMap::new();
+    for image in images.iter_mut() {
+        if let Some(path) = exported.get(&image.path) {
+            image.gaiji = !path.is_empty();
+            continue;
+        }
+        let Some(data) = doc.get_resource_by_path(&image.path) else {
+            continue;
+        };
+        if !image.gaiji {
+            image.gaiji =
+                dimensions(&data).is_some_and(|(w, h)| w <= GAIJI_MAX_SIZE && h <= GAIJI_MAX_SIZE);
+        }
+        if !image.gaiji {
+            // Remembered as not being one, so it isn't looked at again.
+            exported.insert(image.path.clone(), String::new());
+            continue;
+        }
+        let Ok(decoded) = image::load_from_memory(&data) else {
+            continue;
+        };
+        let width = (decoded.width() * height / decoded.height().max(1)).max(1);
+        let resized = decoded.resize_exact(width, height, image::imageops::FilterType::Lanczos3);
+        let stem = image
+            .path
+            .file_stem()
+            .map(|s| s.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let file_name = format!("{}_{}.png", image_name(&stem), exported.len());
+        std::fs::create_dir_all(&folder).unwrap();
+        resized.to_rgba8().save(folder.join(&file_name)).unwrap();
+        exported.insert(image.path.clone(), format!("{GAIJI_FOLDER}/{file_name}"));
+    }
+    exported.retain(|_, path| !path.is_empty());
+    exported
+}
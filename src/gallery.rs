@@ -0,0 +1,132 @@
+use std::fmt::Write;
+use std::path::Path;
+
+use super::illustrations::game_size;
+use super::markup::python_string;
+
+/// Thumbnails are shown at this fraction of the game's size, 384x216 in a 1080p game.
+const THUMBNAIL_SCALE: u32 = 5;
+const COLUMNS: usize = 3;
+/// Button of the game menu's navigation new buttons go after.
+const LOAD_BUTTON: &str = "textbutton _(\"Load\") action ShowMenu(\"load\")";
+
+/// A picture of the gallery.
+#[derive(Debug, Clone)]
+pub struct GalleryEntry {
+    /// File of the image, relative to `game/`.
+    pub file: String,
+    /// Chapter it comes from, written under it.
+    pub caption: Option<String>,
+    /// Whether it's there from the start (the cover) rather than once the reading gets to it.
+    pub unlocked: bool,
+}
+
+/// Name of the gallery button of the image exported to `file`, also used for its `persistent`
+/// flag.
+fn button_name(file: &str) -> String {
+    let stem = Path::new(file)
+        .file_stem()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .chars()
+        .map(|c| if c.is_alphanumeric() { c } else { '_' })
+        .collect::<String>();
+    format!("gallery_{stem}")
+}
+
+/// Script line unlocking the image exported to `file` in the gallery, for when the reading gets
+/// to it.
+pub fn unlock_line(file: &str) -> String {
+    format!("    $ persistent.{} = True\n", button_name(file))
+}
+
+/// Writes `gallery.rpy`, a gallery screen of `entries` in their order, and adds a button to it
+/// in the game menu's navigation of `screens.rpy`. Returns whether the button could be added.
+pub fn write_gallery(game_folder: &Path, entries: &[GalleryEntry]) -> bool {
+    let mut script = String::from("init python:\n    illustration_gallery = Gallery()\n");
+    let mut thumbnails = vec![];
+    for entry in entries {
+        let name = button_name(&entry.file);
+        writeln!(script, "    illustration_gallery.button(\"{name}\")").unwrap();
+        if !entry.unlocked {
+            writeln!(
+                script,
+                "    illustration_gallery.condition(\"persistent.{name}\")"
+            )
+            .unwrap();
+        }
+        writeln!(
+            script,
+            "    illustration_gallery.image({})",
+            python_string(&entry.file, false)
+        )
+        .unwrap();
+        thumbnails.push(format!(
+            "({}, {}, {})",
+            python_string(&name, false),
+            python_string(&entry.file, false),
+            entry
+                .caption
+                .as_deref()
+                .map_or(String::from("None"), |c| python_string(c, true))
+        ));
+    }
+    let (width, height) = game_size(game_folder);
+    let (width, height) = (width / THUMBNAIL_SCALE, height / THUMBNAIL_SCALE);
+    write!(
+        script,
+        "
+define illustration_thumbnails = [
+    {}
+]
+
+screen illustration_gallery():
+    tag menu
+    use game_menu(_(\"Gallery\")):
+        vpgrid:
+            cols {COLUMNS}
+            allow_underfull True
+            spacing 20
+            draggable True
+            mousewheel True
+            scrollbars \"vertical\"
+            for name, file, caption in illustration_thumbnails:
+                vbox:
+                    spacing 5
+                    add illustration_gallery.make_button(name, Transform(file, fit=\"contain\", xysize=({width}, {height})), locked=Solid(\"#0008\", xysize=({width}, {height})))
+                    if caption:
+                        text caption size 20 xmaximum {width}
+",
+        thumbnails.join(",\n    ")
+    )
+    .unwrap();
+    std::fs::write(game_folder.join("gallery.rpy"), script).unwrap();
+
+    add_navigation_button(game_folder, "Gallery", "illustration_gallery")
+}
+
+/// Adds a button showing `screen` to the game menu's navigation of `screens.rpy`, after the one
+/// of the load screen. Returns whether it's there.
+pub fn add_navigation_button(game_folder: &Path, label: &str, screen: &str) -> bool {
+    let button = format!("textbutton _(\"{label}\") action ShowMenu(\"{screen}\")");
+    let screens = game_folder.join("screens.rpy");
+    let Ok(navigation) = std::fs::read_to_string(&screens) else {
+        return false;
+    };
+    if navigation.contains(&button) {
+        return true;
+    }
+    let Some(load) = navigation.find(LOAD_BUTTON) else {
+        return false;
+    };
+    let line_start = navigation[..load].rfind('\n').map_or(0, |n| n + 1);
+    let indent = &navigation[line_start..load];
+    let end = load + LOAD_BUTTON.len();
+    let navigation = format!(
+        "{}\n{indent}{button}{}",
+        &navigation[..end],
+        &navigation[end..]
+    );
+    std::fs::write(&screens, navigation).unwrap();
+    true
+}
@@ -0,0 +1,476 @@
+use image::imageops::{self, FilterType};
+use image::{Rgba, RgbaImage};
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write;
+use std::path::Path;
+
+use super::align::LineAlignment;
+use super::epub_text::{BookImage, EpubText};
+
+/// Placements less sure than this are flagged as ambiguous in the report.
+const LOW_CONFIDENCE: f32 = 0.5;
+
+/// An illustration of the book and the script line it goes before.
+#[derive(Debug, Clone)]
+pub struct Placement {
+    pub image: BookImage,
+    /// Line the image is shown before, the number of lines for after the last one.
+    pub line: usize,
+    pub confidence: f32,
+    /// How the image was placed when its spot in the text couldn't be found, for review.
+    pub fallback: Option<&'static str>,
+}
+
+/// Anchors every illustration (gaiji left out) on the line where its spot in the book's text
+/// went. Images with no matched text anywhere near them come back separately.
+pub fn place_illustrations(
+    alignment: &LineAlignment,
+    images: &[BookImage],
+) -> (Vec<Placement>, Vec<BookImage>) {
+    let mut placements = vec![];
+    let mut unanchored = vec![];
+    for image in images.iter().filter(|i| !i.gaiji) {
+        match alignment.anchor_point(image.position) {
+            Some((line, confidence)) => placements.push(Placement {
+                image: image.clone(),
+                line,
+                confidence,
+                fallback: None,
+            }),
+            None => unanchored.push(image.clone()),
+        }
+    }
+    (placements, unanchored)
+}
+
+/// Puts the images [`place_illustrations`] couldn't anchor somewhere sensible: between the
+/// placed images around them in the same chapter, else at the start of their chapter (the first
+/// line its text went to), else right after the last placed image before them. Returns the
+/// images none of that works for.
+pub fn place_unanchored(
+    alignment: &LineAlignment,
+    book: &EpubText,
+    placements: &mut Vec<Placement>,
+    unanchored: Vec<BookImage>,
+) -> Vec<BookImage> {
+    let mut anchored: Vec<Placement> = placements.clone();
+    anchored.sort_by_key(|p| p.image.position);
+    let mut remaining = vec![];
+    for image in unanchored {
+        let index = anchored.partition_point(|p| p.image.position <= image.position);
+        let previous = index.checked_sub(1).map(|i| &anchored[i]);
+        let next = anchored.get(index);
+        let same_chapter = |p: &&Placement| p.image.spine == image.spine;
+        let placed = match (previous.filter(same_chapter), next.filter(same_chapter)) {
+            (Some(previous), Some(next)) => {
+                let between = (image.position - previous.image.position) as f32
+                    / (next.image.position - previous.image.position).max(1) as f32;
+                let lines = next.line.saturating_sub(previous.line) as f32;
+                Some((
+                    previous.line + (lines * between).round() as usize,
+                    "between its neighbours",
+                ))
+            }
+            _ => alignment
+                .map_span_pieces(book.spine_range(image.spine))
+                .first()
+                .map(|(line, _)| (*line, "chapter start"))
+                .or_else(|| previous.map(|p| (p.line, "after the previous image"))),
+        };
+        match placed {
+            Some((line, fallback)) => placements.push(Placement {
+                image,
+                line,
+                confidence: 0.0,
+                fallback: Some(fallback),
+            }),
+            None => remaining.push(image),
+        }
+    }
+    placements.sort_by_key(|p| (p.line, p.image.position));
+    remaining
+}
+
+/// Ren'Py image name made from an epub file name: lowercase ASCII letters, digits and
+/// underscores behind a `book_` prefix, so it never starts with a digit. What's left of
+/// `挿絵 1` or `p-012` is `book_1` and `book_p_012`.
+pub fn image_name(stem: &str) -> String {
+    let mut name = String::from("book_");
+    for c in stem.chars().flat_map(char::to_lowercase) {
+        if c.is_ascii_alphanumeric() {
+            name.push(c);
+        } else if !name.ends_with('_') {
+            name.push('_');
+        }
+    }
+    match name.trim_end_matches('_') {
+        "book" => String::from("book_image"),
+        name => name.to_string(),
+    }
+}
+
+/// Gives every illustration a Ren'Py image name and a file of its own under `images/`
+/// (relative to `game/`, named after the image), by manifest id. Books can have images of the
+/// same name in different folders, so a name that's already taken gets a number.
+pub fn image_files<'a>(
+    images: impl Iterator<Item = &'a BookImage>,
+    format: ImageFormat,
+) -> HashMap<String, String> {
+    let mut files = HashMap::new();
+    let mut names = HashSet::new();
+    for image in images {
+        let Some(id) = &image.id else {
+            continue;
+        };
+        if files.contains_key(id) {
+            continue;
+        }
+        let stem = image_name(
+            &image
+                .path
+                .file_stem()
+                .map_or_else(|| id.clone(), |s| s.to_string_lossy().into_owned()),
+        );
+        let mut unique = stem.clone();
+        let mut n = 1;
+        while !names.insert(unique.clone()) {
+            n += 1;
+            unique = format!("{stem}_{n}");
+        }
+        files.insert(
+            id.clone(),
+            format!("images/{unique}.{}", format.extension()),
+        );
+    }
+    files
+}
+
+/// How an illustration is shown when the reading gets to it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ImagePresentation {
+    /// Blurred, with a menu asking whether to show it in full.
+    BlurGate,
+    /// In full straight away.
+    Direct,
+    /// A thumbnail over the text, with a menu to zoom it to full size.
+    InlineSmall,
+    /// Not shown while reading, only defined for the gallery.
+    GalleryOnly,
+    /// A Ren'Py snippet of the user's, with the placeholders of [`ImageTemplate::render`].
+    Custom(String),
+}
+
+const BLUR_GATE: &str = r#"    image {name} = "{file}"
+    window hide
+    nvl hide
+    scene {name}:
+        blur 128
+    with {transition}
+    pause
+    menu (nvl=True):
+        "{question}"
+        "{show}":
+            window hide
+            nvl hide
+            scene {name}:
+                blur 0
+            with {transition}
+            pause
+        "{skip}":
+            pass
+    window show
+"#;
+
+const DIRECT: &str = r#"    image {name} = "{file}"
+    window hide
+    nvl hide
+    scene {name}
+    with {transition}
+    pause
+    window show
+"#;
+
+const INLINE_SMALL: &str = r#"    image {name} = "{file}"
+    show {name} at truecenter:
+        zoom 0.3
+    with {transition}
+    menu (nvl=True):
+        "{question}"
+        "{show}":
+            window hide
+            nvl hide
+            show {name} at truecenter:
+                zoom 1.0
+            with {transition}
+            pause
+        "{skip}":
+            pass
+    hide {name}
+    with {transition}
+    window show
+"#;
+
+const GALLERY_ONLY: &str = r#"    image {name} = "{file}"
+"#;
+
+/// The script shown for every illustration.
+#[derive(Debug, Clone)]
+pub struct ImageTemplate {
+    pub presentation: ImagePresentation,
+    /// Question of the menu, for the presentations that ask.
+    pub question: String,
+    /// Menu choice showing the image in full.
+    pub show: String,
+    /// Menu choice going on without it.
+    pub skip: String,
+    /// Ren'Py transition the image comes in with, e.g. `dissolve`. None when empty.
+    pub transition: String,
+}
+
+impl Default for ImageTemplate {
+    fn default() -> Self {
+        Self {
+            presentation: ImagePresentation::BlurGate,
+            question: String::from("Display Image?"),
+            show: String::from("Yes"),
+            skip: String::from("No"),
+            transition: String::new(),
+        }
+    }
+}
+
+impl ImageTemplate {
+    /// The transition as written after `with`.
+    pub fn transition(&self) -> &str {
+        match self.transition.trim() {
+            "" => "None",
+            transition => transition,
+        }
+    }
+
+    /// Script that shows the illustration exported to `file`. Templates can use `{name}` (the
+    /// Ren'Py image name), `{file}`, `{transition}`, and `{question}`, `{show}` and `{skip}`
+    /// for the menu's labels.
+    pub fn render(&self, file: &str) -> String {
+        let template = match &self.presentation {
+            ImagePresentation::BlurGate => BLUR_GATE,
+            ImagePresentation::Direct => DIRECT,
+            ImagePresentation::InlineSmall => INLINE_SMALL,
+            ImagePresentation::GalleryOnly => GALLERY_ONLY,
+            ImagePresentation::Custom(snippet) => snippet,
+        };
+        let name = Path::new(file)
+            .file_stem()
+            .unwrap_or_default()
+            .to_string_lossy();
+        let label = |text: &str| text.replace('\\', "\\\\").replace('"', "\\\"");
+        let transition = self.transition();
+        let mut block = template
+            .replace("{name}", &name)
+            .replace("{file}", file)
+            .replace("{transition}", transition)
+            .replace("{question}", &label(&self.question))
+            .replace("{show}", &label(&self.show))
+            .replace("{skip}", &label(&self.skip));
+        if !block.ends_with('\n') {
+            block.push('\n');
+        }
+        block
+    }
+}
+
+/// Size of a new Ren'Py project.
+const DEFAULT_GAME_SIZE: (u32, u32) = (1920, 1080);
+/// The blurred background is made at this fraction of the game's size, which blurs for free.
+const BLUR_SCALE: u32 = 16;
+/// Smallest side illustrations can be exported at.
+const MIN_EXPORT_SIZE: u32 = 16;
+
+/// How an illustration is fit to the game's size.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageFit {
+    /// Whole, on the colour of its edges.
+    Letterbox,
+    /// Whole, over a blurred copy of itself filling the screen.
+    BlurredFill,
+    /// Filling the screen, what goes past it cut off.
+    Crop,
+    /// Whole and never made bigger than it is, on a transparent background.
+    NoUpscale,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageFormat {
+    /// Lossless, the only WebP the encoder writes, so usually bigger than the book's JPEGs.
+    WebP,
+    Png,
+}
+
+impl ImageFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ImageFormat::WebP => "webp",
+            ImageFormat::Png => "png",
+        }
+    }
+}
+
+/// How illustrations are written to the game.
+#[derive(Debug, Clone, Copy)]
+pub struct ImageExport {
+    pub fit: ImageFit,
+    pub format: ImageFormat,
+    /// Size to fit them to, the game's own when `None`.
+    pub size: Option<(u32, u32)>,
+}
+
+impl Default for ImageExport {
+    fn default() -> Self {
+        Self {
+            fit: ImageFit::Letterbox,
+            format: ImageFormat::Png,
+            size: None,
+        }
+    }
+}
+
+/// Export size written as `1280x720`, `None` when `text` is empty.
+pub fn parse_export_size(text: &str) -> Result<Option<(u32, u32)>, String> {
+    if text.trim().is_empty() {
+        return Ok(None);
+    }
+    let size = text
+        .split_once('x')
+        .and_then(|(w, h)| Some((w.trim().parse().ok()?, h.trim().parse().ok()?)));
+    match size {
+        Some((width, height)) if width >= MIN_EXPORT_SIZE && height >= MIN_EXPORT_SIZE => {
+            Ok(Some((width, height)))
+        }
+        Some(_) => Err(format!(
+            "{text} is too small for illustrations, they need at least {MIN_EXPORT_SIZE}x{MIN_EXPORT_SIZE}"
+        )),
+        None => Err(format!("{text} isn't a size, write it as 1280x720")),
+    }
+}
+
+/// Size of the game, from the `gui.init(width, height)` of `gui.rpy`.
+pub fn game_size(game_folder: &Path) -> (u32, u32) {
+    std::fs::read_to_string(game_folder.join("gui.rpy"))
+        .ok()
+        .and_then(|gui| {
+            gui.lines().find_map(|l| {
+                let (width, height) = l
+                    .trim()
+                    .strip_prefix("gui.init(")?
+                    .strip_suffix(')')?
+                    .split_once(',')?;
+                Some((width.trim().parse().ok()?, height.trim().parse().ok()?))
+            })
+        })
+        .unwrap_or(DEFAULT_GAME_SIZE)
+}
+
+/// Average colour of the outermost pixels of the image.
+fn edge_colour(image: &RgbaImage) -> Rgba<u8> {
+    let (width, height) = image.dimensions();
+    let mut sum = [0u64; 4];
+    let mut count = 0;
+    for (x, y, pixel) in image.enumerate_pixels() {
+        if x == 0 || y == 0 || x + 1 == width || y + 1 == height {
+            for (s, c) in sum.iter_mut().zip(pixel.0) {
+                *s += c as u64;
+            }
+            count += 1;
+        }
+    }
+    Rgba(sum.map(|s| (s / count.max(1)) as u8))
+}
+
+/// Writes the illustration `data` to `path` at `size`, fit as `fit`, transparency kept. The
+/// format is the one of `path`'s extension.
+pub fn write_image(
+    data: &[u8],
+    path: &Path,
+    size: (u32, u32),
+    fit: ImageFit,
+) -> image::ImageResult<()> {
+    let (width, height) = size;
+    let filter = FilterType::Lanczos3;
+    let image = image::load_from_memory(data)?;
+    let canvas = match fit {
+        ImageFit::Crop => image.resize_to_fill(width, height, filter).into_rgba8(),
+        _ => {
+            let fitted =
+                if fit == ImageFit::NoUpscale && image.width() <= width && image.height() <= height
+                {
+                    image.to_rgba8()
+                } else {
+                    image.resize(width, height, filter).into_rgba8()
+                };
+            let mut canvas = match fit {
+                ImageFit::BlurredFill => image
+                    .resize_to_fill(width / BLUR_SCALE, height / BLUR_SCALE, filter)
+                    .blur(2.0)
+                    .resize_exact(width, height, FilterType::Triangle)
+                    .into_rgba8(),
+                ImageFit::Letterbox => RgbaImage::from_pixel(width, height, edge_colour(&fitted)),
+                _ => RgbaImage::new(width, height),
+            };
+            let x = (width - fitted.width()) / 2;
+            let y = (height - fitted.height()) / 2;
+            imageops::overlay(&mut canvas, &fitted, x as i64, y as i64);
+            canvas
+        }
+    };
+    canvas.save(path)
+}
+
+/// Writes `image_names.tsv` in `folder`: the manifest id of every exported illustration, its
+/// path in the epub, then its Ren'Py image name and file.
+pub fn write_image_names(folder: &Path, files: &HashMap<String, String>, images: &[BookImage]) {
+    let mut rows: Vec<(&String, &String)> = files.iter().collect();
+    rows.sort_by_key(|(_, file)| *file);
+    let mut mapping = String::from("id\tpath\tname\tfile\n");
+    for (id, file) in rows {
+        let path = images
+            .iter()
+            .find(|i| i.id.as_ref() == Some(id))
+            .map(|i| i.path.display().to_string())
+            .unwrap_or_default();
+        let name = Path::new(file).file_stem().unwrap_or_default();
+        writeln!(mapping, "{id}\t{path}\t{}\t{file}", name.to_string_lossy()).unwrap();
+    }
+    std::fs::write(folder.join("image_names.tsv"), mapping).unwrap();
+}
+
+/// Writes `image_placements.txt` in `folder`: every image with the line it was put before and
+/// how sure that is, ambiguous ones and those placed by a fallback marked, then the images that
+/// couldn't be placed at all.
+pub fn write_placement_report(
+    folder: &Path,
+    placements: &[Placement],
+    unanchored: &[BookImage],
+    lines: &[String],
+) {
+    let mut report = String::new();
+    for placement in placements {
+        writeln!(
+            report,
+            "{}\tline {}\t{:.0}%{}\t{}",
+            placement.image.path.display(),
+            placement.line + 1,
+            placement.confidence * 100.0,
+            match placement.fallback {
+                Some(fallback) => format!("\tFALLBACK ({fallback})"),
+                None if placement.confidence < LOW_CONFIDENCE => String::from("\tAMBIGUOUS"),
+                None => String::new(),
+            },
+            lines.get(placement.line).map_or("(end)", |l| l.as_str())
+        )
+        .unwrap();
+    }
+    for image in unanchored {
+        writeln!(report, "{}\tnot placed", image.path.display()).unwrap();
+    }
+    std::fs::write(folder.join("image_placements.txt"), report).unwrap();
+}
@@ -0,0 +1,130 @@
+use regex::Regex;
+
+use super::dictionary::{is_kana, is_kanji};
+use super::markup::{Markup, Span};
+
+/// Reading notations to look for in the subtitles' own text.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RubyNotations {
+    /// `漢字(かんじ)` and `漢字（かんじ）`, when the parentheses hold nothing but kana.
+    pub parentheses: bool,
+    /// Aozora Bunko style: `｜漢字《かんじ》` (or `|`), and `漢字《かんじ》` on a run of kanji.
+    pub aozora: bool,
+    /// ASS karaoke syllables carrying furigana, in `.ass` subtitles: `{\k20}漢|かん{\k20}字|じ`.
+    pub ass_karaoke: bool,
+}
+
+impl RubyNotations {
+    pub fn any(&self) -> bool {
+        self.parentheses || self.aozora || self.ass_karaoke
+    }
+}
+
+const RUBY_MARKERS: [char; 2] = ['|', '｜'];
+
+fn is_reading(text: &str) -> bool {
+    !text.is_empty() && text.chars().all(|c| is_kana(c) || c == 'ー')
+}
+
+/// Syllables of a karaoke line, with its override tags (`{\k20}`, `{\pos(…)}`…) dropped.
+fn karaoke(text: &str, tags: &Regex) -> (String, Vec<Span>) {
+    let mut line = String::new();
+    let mut spans = vec![];
+    for syllable in tags.split(text) {
+        let start = line.chars().count();
+        match syllable.split_once('|') {
+            Some((base, reading)) if !base.is_empty() && !reading.is_empty() => {
+                line.push_str(base);
+                spans.push(Span {
+                    range: start..start + base.chars().count(),
+                    markup: Markup::Ruby(reading.to_string()),
+                });
+            }
+            _ => line.push_str(syllable),
+        }
+    }
+    (line, spans)
+}
+
+fn aozora_and_parentheses(text: &str, notations: &RubyNotations) -> (String, Vec<Span>) {
+    let chars: Vec<char> = text.chars().collect();
+    let mut line: Vec<char> = Vec::with_capacity(chars.len());
+    let mut spans: Vec<Span> = vec![];
+    // Where the last `｜` is, which starts the next base if a reading follows.
+    let mut marker: Option<usize> = None;
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let closing = match c {
+            '《' if notations.aozora => Some('》'),
+            '(' if notations.parentheses => Some(')'),
+            '（' if notations.parentheses => Some('）'),
+            _ => None,
+        };
+        if c == '\n' {
+            // A `｜` doesn't reach past its line.
+            marker = None;
+        }
+        if notations.aozora && RUBY_MARKERS.contains(&c) {
+            marker = Some(line.len());
+            line.push(c);
+            i += 1;
+            continue;
+        }
+        if let Some(closing) = closing {
+            // The `｜` goes with this notation, whether it's a reading, something else or never
+            // closed.
+            let marked = marker.take();
+            // Closed on the same line, before another one opens.
+            let end = chars[i + 1..]
+                .iter()
+                .take_while(|x| **x != c && **x != '\n')
+                .position(|x| *x == closing)
+                .map(|p| i + 1 + p);
+            if let Some(end) = end {
+                let reading: String = chars[i + 1..end].iter().collect();
+                let previous_end = spans.last().map_or(0, |s| s.range.end);
+                let run = line.iter().rev().take_while(|c| is_kanji(**c)).count();
+                let start = match marked {
+                    Some(m) if m + 1 < line.len() => m,
+                    _ => (line.len() - run).max(previous_end),
+                };
+                // Parentheses are also just parentheses, so only kana ones count as readings.
+                let valid = start < line.len() && (c == '《' || is_reading(&reading));
+                if valid && !reading.trim().is_empty() {
+                    if marked == Some(start) {
+                        line.remove(start);
+                    }
+                    spans.push(Span {
+                        range: start..line.len(),
+                        markup: Markup::Ruby(reading.trim().to_string()),
+                    });
+                    i = end + 1;
+                    continue;
+                }
+            }
+        }
+        line.push(c);
+        i += 1;
+    }
+    (line.into_iter().collect(), spans)
+}
+
+/// Takes the readings written in the subtitle lines out of them, leaving each line as it should
+/// be shown, and returns the rubies to put on every line.
+pub fn extract_rubies(lines: &mut [String], notations: &RubyNotations) -> Vec<Vec<Span>> {
+    let tags = Regex::new(r"\{[^}]*\}").unwrap();
+    let karaoke_tag = Regex::new(r"\{[^}]*\\[kK][fo]?\d").unwrap();
+    lines
+        .iter_mut()
+        .map(|line| {
+            let (text, spans) = if notations.ass_karaoke && karaoke_tag.is_match(line) {
+                karaoke(line, &tags)
+            } else {
+                aozora_and_parentheses(line, notations)
+            };
+            *line = text;
+            spans
+        })
+        .collect()
+}
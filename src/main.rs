@@ -7,8 +7,8 @@ use relm4::{
     gtk::{
         self,
         prelude::{
-            BoxExt, ButtonExt, EditableExt, EntryBufferExtManual, EntryExt, GtkWindowExt,
-            OrientableExt, TextBufferExt, TextViewExt, WidgetExt,
+            BoxExt, ButtonExt, CheckButtonExt, EditableExt, EntryBufferExtManual, EntryExt,
+            GtkWindowExt, OrientableExt, TextBufferExt, TextViewExt, WidgetExt,
         },
         Adjustment, EntryBuffer, FileFilter,
     },
@@ -22,29 +22,48 @@ use relm4_components::{
 
 use worker::{AsyncHandler, AsyncHandlerInMsg};
 
-use crate::process::MyArgs;
+use crate::process::{
+    cues::MergeThresholds,
+    dictionary::FuriganaRepeat,
+    epub_text::parse_style_classes,
+    illustrations::{
+        parse_export_size, ImageExport, ImageFit, ImageFormat, ImagePresentation, ImageTemplate,
+    },
+    inline_ruby::RubyNotations,
+    metadata::{write_options, GameMetadata},
+    rpa::RpaGrouping,
+    ClipLayout, MyArgs,
+};
 
 mod epub_process;
 mod process;
 mod worker;
 
-pub fn update_save_folder(prefix: &str, game_path: &PathBuf) {
-    let mut game_path = game_path.clone();
-    game_path.push("options.rpy");
-    if let Ok(options_file) = std::fs::read_to_string(&game_path) {
-        let options_file = options_file.replace(
-            "AudiobookToRenpy-1689430246",
-            &prefix.to_string().replace(" ", "-"),
-        );
-        std::fs::write(&game_path, &options_file).unwrap();
-    }
-}
-
 struct AppModel {
     open_srt: Controller<OpenButton>,
     srt_path: PathBuf,
     open_epub: Controller<OpenButton>,
     epub_path: Option<PathBuf>,
+    use_epub_text: bool,
+    style_classes: EntryBuffer,
+    open_dictionary: Controller<OpenButton>,
+    dictionary_path: Option<PathBuf>,
+    furigana_repeat: FuriganaRepeat,
+    open_known_kanji: Controller<OpenButton>,
+    known_kanji_path: Option<PathBuf>,
+    open_overrides: Controller<OpenButton>,
+    overrides_path: Option<PathBuf>,
+    subtitle_rubies: RubyNotations,
+    image_presentation: ImagePresentation,
+    open_image_template: Controller<OpenButton>,
+    image_template_path: Option<PathBuf>,
+    image_question: EntryBuffer,
+    image_show: EntryBuffer,
+    image_skip: EntryBuffer,
+    image_transition: EntryBuffer,
+    image_fit: ImageFit,
+    image_format: ImageFormat,
+    image_size: EntryBuffer,
     open_audio: Controller<OpenButton>,
     audio_path: PathBuf,
     audio_ext: Option<AudioExt>,
@@ -53,6 +72,15 @@ struct AppModel {
     offset_before: f64,
     gain: f64,
     speed: f64,
+    clip_layout: ClipLayout,
+    clip_bucket_size: f64,
+    split_sentences: bool,
+    refine_splits: bool,
+    merge_fragments: bool,
+    merge_thresholds: MergeThresholds,
+    pack: bool,
+    pack_by_type: bool,
+    pack_remove_loose: bool,
     show_button: bool,
     sensitive: bool,
     worker: WorkerController<AsyncHandler>,
@@ -69,6 +97,10 @@ pub enum DialogOrigin {
     Audio,
     Srt,
     Epub,
+    Dictionary,
+    KnownKanji,
+    Overrides,
+    ImageTemplate,
 }
 
 #[derive(Debug)]
@@ -85,6 +117,25 @@ pub enum AppInMsg {
     UpdateOffset(f64),
     UpdateGain(f64),
     UpdateSpeed(f64),
+    UpdateUseEpubText(bool),
+    UpdateFuriganaRepeat(u32),
+    UpdateParenthesesRubies(bool),
+    UpdateAozoraRubies(bool),
+    UpdateKaraokeRubies(bool),
+    UpdateImagePresentation(u32),
+    UpdateImageFit(u32),
+    UpdateImageFormat(u32),
+    UpdateClipLayout(u32),
+    UpdateClipBucketSize(f64),
+    UpdateSplitSentences(bool),
+    UpdateRefineSplits(bool),
+    UpdateMergeFragments(bool),
+    UpdateMergeDuration(f64),
+    UpdateMergeChars(f64),
+    UpdateMergeBrackets(bool),
+    UpdatePack(bool),
+    UpdatePackByType(bool),
+    UpdatePackRemoveLoose(bool),
     Open(PathBuf, DialogOrigin),
     StartConversion(f64, f64),
     StartAudioSplit,
@@ -111,7 +162,9 @@ impl SimpleComponent for AppModel {
     ) -> ComponentParts<Self> {
         let srt_filter = FileFilter::new();
         srt_filter.add_pattern("*.srt");
-        srt_filter.set_name(Some("Subtitle files (.srt)"));
+        srt_filter.add_pattern("*.ass");
+        srt_filter.add_pattern("*.ssa");
+        srt_filter.set_name(Some("Subtitle files (.srt, .ass)"));
 
         let open_srt = OpenButton::builder()
             .launch(OpenButtonSettings {
@@ -155,6 +208,96 @@ impl SimpleComponent for AppModel {
                 AppInMsg::Open(path, DialogOrigin::Epub)
             });
 
+        let dictionary_filter = FileFilter::new();
+        dictionary_filter.add_pattern("*.csv");
+        dictionary_filter.set_name(Some("Dictionary files (.csv)"));
+
+        let open_dictionary = OpenButton::builder()
+            .launch(OpenButtonSettings {
+                dialog_settings: OpenDialogSettings {
+                    folder_mode: false,
+                    cancel_label: String::from("Cancel"),
+                    accept_label: String::from("Select"),
+                    create_folders: true,
+                    is_modal: true,
+                    filters: vec![dictionary_filter],
+                },
+                text: "Open file",
+                recently_opened_files: None,
+                max_recent_files: 0,
+            })
+            .forward(sender.input_sender(), |path| {
+                AppInMsg::Open(path, DialogOrigin::Dictionary)
+            });
+
+        let known_kanji_filter = FileFilter::new();
+        known_kanji_filter.add_pattern("*.txt");
+        known_kanji_filter.set_name(Some("Text files (.txt)"));
+
+        let open_known_kanji = OpenButton::builder()
+            .launch(OpenButtonSettings {
+                dialog_settings: OpenDialogSettings {
+                    folder_mode: false,
+                    cancel_label: String::from("Cancel"),
+                    accept_label: String::from("Select"),
+                    create_folders: true,
+                    is_modal: true,
+                    filters: vec![known_kanji_filter],
+                },
+                text: "Open file",
+                recently_opened_files: None,
+                max_recent_files: 0,
+            })
+            .forward(sender.input_sender(), |path| {
+                AppInMsg::Open(path, DialogOrigin::KnownKanji)
+            });
+
+        let overrides_filter = FileFilter::new();
+        overrides_filter.add_pattern("*.tsv");
+        overrides_filter.add_pattern("*.txt");
+        overrides_filter.set_name(Some("Furigana overrides (.tsv, .txt)"));
+
+        let open_overrides = OpenButton::builder()
+            .launch(OpenButtonSettings {
+                dialog_settings: OpenDialogSettings {
+                    folder_mode: false,
+                    cancel_label: String::from("Cancel"),
+                    accept_label: String::from("Select"),
+                    create_folders: true,
+                    is_modal: true,
+                    filters: vec![overrides_filter],
+                },
+                text: "Open file",
+                recently_opened_files: None,
+                max_recent_files: 0,
+            })
+            .forward(sender.input_sender(), |path| {
+                AppInMsg::Open(path, DialogOrigin::Overrides)
+            });
+
+        let image_template_filter = FileFilter::new();
+        image_template_filter.add_pattern("*.rpy");
+        image_template_filter.add_pattern("*.txt");
+        image_template_filter.set_name(Some("Ren'Py snippets (.rpy, .txt)"));
+
+        let open_image_template = OpenButton::builder()
+            .launch(OpenButtonSettings {
+                dialog_settings: OpenDialogSettings {
+                    folder_mode: false,
+                    cancel_label: String::from("Cancel"),
+                    accept_label: String::from("Select"),
+                    create_folders: true,
+                    is_modal: true,
+                    filters: vec![image_template_filter],
+                },
+                text: "Open file",
+                recently_opened_files: None,
+                max_recent_files: 0,
+            })
+            .forward(sender.input_sender(), |path| {
+                AppInMsg::Open(path, DialogOrigin::ImageTemplate)
+            });
+
         let audio_filter = FileFilter::new();
         audio_filter.add_pattern("*.mp3");
         audio_filter.add_pattern("*.m4b");
@@ -185,9 +328,29 @@ impl SimpleComponent for AppModel {
             open_srt,
             open_audio,
             open_epub,
+            open_dictionary,
+            open_known_kanji,
+            open_overrides,
+            open_image_template,
             audio_ext: None,
             buffer: gtk::TextBuffer::new(None),
             epub_path: None,
+            use_epub_text: false,
+            style_classes: EntryBuffer::new(None::<&str>),
+            dictionary_path: None,
+            furigana_repeat: FuriganaRepeat::Always,
+            known_kanji_path: None,
+            overrides_path: None,
+            subtitle_rubies: RubyNotations::default(),
+            image_presentation: ImagePresentation::BlurGate,
+            image_template_path: None,
+            image_question: EntryBuffer::new(Some(ImageTemplate::default().question.as_str())),
+            image_show: EntryBuffer::new(Some(ImageTemplate::default().show.as_str())),
+            image_skip: EntryBuffer::new(Some(ImageTemplate::default().skip.as_str())),
+            image_transition: EntryBuffer::new(None::<&str>),
+            image_fit: ImageFit::Letterbox,
+            image_format: ImageFormat::Png,
+            image_size: EntryBuffer::new(None::<&str>),
             srt_path: PathBuf::from(""),
             audio_path: PathBuf::from(""),
             show_button: false,
@@ -198,6 +361,15 @@ impl SimpleComponent for AppModel {
             sensitive: true,
             gain: 1.0,
             speed: 1.0,
+            clip_layout: ClipLayout::Chapters,
+            clip_bucket_size: 1000.0,
+            split_sentences: false,
+            refine_splits: false,
+            merge_fragments: false,
+            merge_thresholds: MergeThresholds::default(),
+            pack: false,
+            pack_by_type: true,
+            pack_remove_loose: false,
         };
 
         let widgets = view_output!();
@@ -225,17 +397,63 @@ impl SimpleComponent for AppModel {
                 ));
             }
             AppInMsg::StartAudioSplit => {
+                let image_size = match parse_export_size(&self.image_size.text()) {
+                    Ok(size) => size,
+                    Err(err) => {
+                        self.buffer.insert_at_cursor(&format!("{err}\n"));
+                        self.sensitive = true;
+                        return;
+                    }
+                };
+                let presentation = match (&self.image_presentation, &self.image_template_path) {
+                    (ImagePresentation::Custom(_), Some(path)) => {
+                        match std::fs::read_to_string(path) {
+                            Ok(template) => ImagePresentation::Custom(template),
+                            Err(err) => {
+                                self.buffer.insert_at_cursor(&format!(
+                                    "Couldn't read the illustration snippet {}: {err}\n",
+                                    path.display()
+                                ));
+                                self.sensitive = true;
+                                return;
+                            }
+                        }
+                    }
+                    (ImagePresentation::Custom(_), None) => ImagePresentation::BlurGate,
+                    (presentation, _) => presentation.clone(),
+                };
                 //TODO fix
                 let mut game_folder = env::current_dir().unwrap();
                 game_folder.push(self.prefix.text());
                 game_folder.push("game");
                 copy_dir("template", self.prefix.text()).unwrap();
-                update_save_folder(
-                    &self.prefix.text().to_string().replace(" ", "_"),
-                    &game_folder,
-                );
+                if let Err(err) =
+                    write_options(&game_folder, &GameMetadata::named(&self.prefix.text()))
+                {
+                    self.buffer
+                        .insert_at_cursor(&format!("Couldn't update options.rpy: {err}\n"));
+                }
                 let args = MyArgs {
                     epub: self.epub_path.clone(),
+                    use_epub_text: self.use_epub_text,
+                    style_classes: parse_style_classes(&self.style_classes.text()),
+                    furigana_dictionary: self.dictionary_path.clone(),
+                    furigana_repeat: self.furigana_repeat,
+                    known_kanji: self.known_kanji_path.clone(),
+                    furigana_overrides: self.overrides_path.clone(),
+                    subtitle_rubies: self.subtitle_rubies,
+                    image_template: ImageTemplate {
+                        presentation,
+                        question: self.image_question.text().to_string(),
+                        show: self.image_show.text().to_string(),
+                        skip: self.image_skip.text().to_string(),
+                        transition: self.image_transition.text().to_string(),
+                    },
+                    image_export: ImageExport {
+                        fit: self.image_fit,
+                        format: self.image_format,
+                        size: image_size,
+                    },
                     game_folder,
                     audiobook: self.audio_path.clone(),
                     subtitle: self.srt_path.clone(),
@@ -243,7 +461,16 @@ impl SimpleComponent for AppModel {
                     speed: self.speed,
                     gain: self.gain,
                     split: true,
-                    show_buggies: true,
+                    clip_layout: self.clip_layout,
+                    split_sentences: self.split_sentences,
+                    refine_splits_with_silence: self.refine_splits,
+                    merge_fragments: self.merge_fragments.then_some(self.merge_thresholds),
+                    pack: match (self.pack, self.pack_by_type) {
+                        (false, _) => None,
+                        (true, true) => Some(RpaGrouping::ByAssetType),
+                        (true, false) => Some(RpaGrouping::Single),
+                    },
+                    pack_remove_loose: self.pack_remove_loose,
                 };
                 self.worker
                     .emit(AsyncHandlerInMsg::SplitAudio(args, self.audio_path.clone()))
@@ -257,6 +484,88 @@ impl SimpleComponent for AppModel {
             AppInMsg::UpdateSpeed(val) => {
                 self.speed = val;
             }
+            AppInMsg::UpdateUseEpubText(val) => {
+                self.use_epub_text = val;
+            }
+            AppInMsg::UpdateFuriganaRepeat(val) => {
+                self.furigana_repeat = match val {
+                    1 => FuriganaRepeat::FirstPerChapter,
+                    2 => FuriganaRepeat::UnknownKanji,
+                    _ => FuriganaRepeat::Always,
+                };
+            }
+            AppInMsg::UpdateParenthesesRubies(val) => {
+                self.subtitle_rubies.parentheses = val;
+            }
+            AppInMsg::UpdateAozoraRubies(val) => {
+                self.subtitle_rubies.aozora = val;
+            }
+            AppInMsg::UpdateKaraokeRubies(val) => {
+                self.subtitle_rubies.ass_karaoke = val;
+            }
+            AppInMsg::UpdateImagePresentation(val) => {
+                self.image_presentation = match val {
+                    1 => ImagePresentation::Direct,
+                    2 => ImagePresentation::InlineSmall,
+                    3 => ImagePresentation::GalleryOnly,
+                    4 => ImagePresentation::Custom(String::new()),
+                    _ => ImagePresentation::BlurGate,
+                };
+            }
+            AppInMsg::UpdateImageFit(val) => {
+                self.image_fit = match val {
+                    1 => ImageFit::BlurredFill,
+                    2 => ImageFit::Crop,
+                    3 => ImageFit::NoUpscale,
+                    _ => ImageFit::Letterbox,
+                };
+            }
+            AppInMsg::UpdateImageFormat(val) => {
+                self.image_format = match val {
+                    1 => ImageFormat::WebP,
+                    _ => ImageFormat::Png,
+                };
+            }
+            AppInMsg::UpdateClipLayout(val) => {
+                self.clip_layout = match val {
+                    1 => ClipLayout::Buckets(self.clip_bucket_size as usize),
+                    2 => ClipLayout::Flat,
+                    _ => ClipLayout::Chapters,
+                };
+            }
+            AppInMsg::UpdateClipBucketSize(val) => {
+                self.clip_bucket_size = val;
+                if let ClipLayout::Buckets(_) = self.clip_layout {
+                    self.clip_layout = ClipLayout::Buckets(val as usize);
+                }
+            }
+            AppInMsg::UpdateSplitSentences(val) => {
+                self.split_sentences = val;
+            }
+            AppInMsg::UpdateRefineSplits(val) => {
+                self.refine_splits = val;
+            }
+            AppInMsg::UpdateMergeFragments(val) => {
+                self.merge_fragments = val;
+            }
+            AppInMsg::UpdateMergeDuration(val) => {
+                self.merge_thresholds.min_duration = val as u32;
+            }
+            AppInMsg::UpdateMergeChars(val) => {
+                self.merge_thresholds.min_chars = val as usize;
+            }
+            AppInMsg::UpdateMergeBrackets(val) => {
+                self.merge_thresholds.unbalanced_brackets = val;
+            }
+            AppInMsg::UpdatePack(val) => {
+                self.pack = val;
+            }
+            AppInMsg::UpdatePackByType(val) => {
+                self.pack_by_type = val;
+            }
+            AppInMsg::UpdatePackRemoveLoose(val) => {
+                self.pack_remove_loose = val;
+            }
             AppInMsg::Recheck => {
                 self.show_button = self.prefix.length() > 0
                     && !self.audio_path.as_os_str().is_empty()
@@ -274,6 +583,10 @@ impl SimpleComponent for AppModel {
                     }
                     DialogOrigin::Srt => self.srt_path = path,
                     DialogOrigin::Epub => self.epub_path = Some(path),
+                    DialogOrigin::Dictionary => self.dictionary_path = Some(path),
+                    DialogOrigin::KnownKanji => self.known_kanji_path = Some(path),
+                    DialogOrigin::Overrides => self.overrides_path = Some(path),
+                    DialogOrigin::ImageTemplate => self.image_template_path = Some(path),
                 };
                 self.show_button = self.prefix.length() > 0
                     && !self.audio_path.as_os_str().is_empty()
@@ -328,7 +641,7 @@ impl SimpleComponent for AppModel {
                     #[watch]
                     set_sensitive: model.sensitive,
                     gtk::Label {
-                        set_label: "Path to the .srt file"
+                        set_label: "Path to the .srt or .ass file"
 
                     },
                     append = model.open_srt.widget(),
@@ -367,7 +680,191 @@ impl SimpleComponent for AppModel {
                         #[watch]
                         //TODO improve this iirc there’s a option thing in relm dsl
                         set_label: &model.epub_path.clone().unwrap_or_default().to_string_lossy()
-                    }
+                    },
+                    gtk::CheckButton::with_label("Use the epub's wording for the lines") {
+                        #[watch]
+                        set_sensitive: model.epub_path.is_some(),
+                        connect_toggled[sender] => move |x| {
+                            sender.input(AppInMsg::UpdateUseEpubText(x.is_active()))
+                        }
+                    },
+                },
+
+                gtk::Box {
+                    set_spacing: 5,
+                    set_margin_all: 5,
+                    set_orientation: gtk::Orientation::Horizontal,
+                    #[watch]
+                    set_sensitive: model.sensitive && model.epub_path.is_some(),
+                    gtk::Label {
+                        set_label: "Epub classes to styles (class=emphasis/bold/italic, comma separated)"
+                    },
+                    gtk::Entry {
+                        set_buffer: &model.style_classes,
+                        set_placeholder_text: Some("em-sesame=emphasis, gothic=bold"),
+                    },
+                },
+
+                gtk::Box {
+                    set_spacing: 5,
+                    set_margin_all: 5,
+                    set_orientation: gtk::Orientation::Horizontal,
+                    #[watch]
+                    set_sensitive: model.sensitive,
+                    gtk::Label {
+                        set_label: "Dictionary for furigana (optional, IPADIC/UniDic .csv),"
+                    },
+                    append = model.open_dictionary.widget(),
+                    gtk::Label {
+                        #[watch]
+                        set_label: &model.dictionary_path.clone().unwrap_or_default().to_string_lossy()
+                    },
+                    gtk::DropDown::from_strings(&["Every time", "First time in each chapter", "Only for unknown kanji"]) {
+                        #[watch]
+                        set_sensitive: model.dictionary_path.is_some(),
+                        connect_selected_notify[sender] => move |x| {
+                            sender.input(AppInMsg::UpdateFuriganaRepeat(x.selected()))
+                        }
+                    },
+                },
+
+                gtk::Box {
+                    set_spacing: 5,
+                    set_margin_all: 5,
+                    set_orientation: gtk::Orientation::Horizontal,
+                    #[watch]
+                    set_sensitive: model.sensitive && model.furigana_repeat == FuriganaRepeat::UnknownKanji,
+                    gtk::Label {
+                        set_label: "Known kanji list (any text file)"
+                    },
+                    append = model.open_known_kanji.widget(),
+                    gtk::Label {
+                        #[watch]
+                        set_label: &model.known_kanji_path.clone().unwrap_or_default().to_string_lossy()
+                    },
+                },
+
+                gtk::Box {
+                    set_spacing: 5,
+                    set_margin_all: 5,
+                    set_orientation: gtk::Orientation::Horizontal,
+                    #[watch]
+                    set_sensitive: model.sensitive,
+                    gtk::Label {
+                        set_label: "Furigana overrides (optional)"
+                    },
+                    append = model.open_overrides.widget(),
+                    gtk::Label {
+                        #[watch]
+                        set_label: &model.overrides_path.clone().unwrap_or_default().to_string_lossy()
+                    },
+                },
+
+                gtk::Box {
+                    set_spacing: 5,
+                    set_margin_all: 5,
+                    set_orientation: gtk::Orientation::Horizontal,
+                    #[watch]
+                    set_sensitive: model.sensitive,
+                    gtk::Label {
+                        set_label: "Readings written in the subtitles:"
+                    },
+                    gtk::CheckButton::with_label("漢字(かんじ)") {
+                        connect_toggled[sender] => move |x| {
+                            sender.input(AppInMsg::UpdateParenthesesRubies(x.is_active()))
+                        }
+                    },
+                    gtk::CheckButton::with_label("｜漢字《かんじ》") {
+                        connect_toggled[sender] => move |x| {
+                            sender.input(AppInMsg::UpdateAozoraRubies(x.is_active()))
+                        }
+                    },
+                    gtk::CheckButton::with_label("ASS karaoke (漢|かん)") {
+                        connect_toggled[sender] => move |x| {
+                            sender.input(AppInMsg::UpdateKaraokeRubies(x.is_active()))
+                        }
+                    },
+                },
+
+                gtk::Box {
+                    set_spacing: 5,
+                    set_margin_all: 5,
+                    set_orientation: gtk::Orientation::Horizontal,
+                    #[watch]
+                    set_sensitive: model.sensitive && model.epub_path.is_some(),
+                    gtk::Label {
+                        set_label: "Epub illustrations"
+                    },
+                    gtk::DropDown::from_strings(&["Blurred, ask to show", "Shown directly", "Thumbnail, ask to zoom", "Only in the gallery", "Custom snippet"]) {
+                        connect_selected_notify[sender] => move |x| {
+                            sender.input(AppInMsg::UpdateImagePresentation(x.selected()))
+                        }
+                    },
+                    gtk::Box {
+                        set_spacing: 5,
+                        #[watch]
+                        set_sensitive: matches!(model.image_presentation, ImagePresentation::Custom(_)),
+                        append = model.open_image_template.widget(),
+                        gtk::Label {
+                            #[watch]
+                            set_label: &model.image_template_path.clone().unwrap_or_default().to_string_lossy()
+                        },
+                    },
+                },
+
+                gtk::Box {
+                    set_spacing: 5,
+                    set_margin_all: 5,
+                    set_orientation: gtk::Orientation::Horizontal,
+                    #[watch]
+                    set_sensitive: model.sensitive && model.epub_path.is_some(),
+                    gtk::Label {
+                        set_label: "Menu"
+                    },
+                    gtk::Entry {
+                        set_buffer: &model.image_question,
+                    },
+                    gtk::Entry {
+                        set_buffer: &model.image_show,
+                    },
+                    gtk::Entry {
+                        set_buffer: &model.image_skip,
+                    },
+                    gtk::Label {
+                        set_label: "Transition"
+                    },
+                    gtk::Entry {
+                        set_buffer: &model.image_transition,
+                        set_placeholder_text: Some("dissolve"),
+                    },
+                },
+
+                gtk::Box {
+                    set_spacing: 5,
+                    set_margin_all: 5,
+                    set_orientation: gtk::Orientation::Horizontal,
+                    #[watch]
+                    set_sensitive: model.sensitive && model.epub_path.is_some(),
+                    gtk::Label {
+                        set_label: "Illustration files"
+                    },
+                    gtk::DropDown::from_strings(&["Letterbox on the edge colour", "Over a blurred copy", "Cropped to fill", "Letterbox, no upscaling"]) {
+                        connect_selected_notify[sender] => move |x| {
+                            sender.input(AppInMsg::UpdateImageFit(x.selected()))
+                        }
+                    },
+                    gtk::DropDown::from_strings(&["PNG", "WebP (lossless)"]) {
+                        connect_selected_notify[sender] => move |x| {
+                            sender.input(AppInMsg::UpdateImageFormat(x.selected()))
+                        }
+                    },
+                    gtk::Label {
+                        set_label: "Resolution"
+                    },
+                    gtk::Entry {
+                        set_buffer: &model.image_size,
+                        set_placeholder_text: Some("from gui.rpy, e.g. 1280x720"),
+                    },
                 },
 
                 gtk::Box {
@@ -431,6 +928,124 @@ impl SimpleComponent for AppModel {
 
                 },
 
+                gtk::Box {
+                    set_spacing: 5,
+                    set_margin_all: 5,
+                    set_orientation: gtk::Orientation::Horizontal,
+                    #[watch]
+                    set_sensitive: model.sensitive,
+                    gtk::CheckButton::with_label("Split long subtitles into sentences") {
+                        connect_toggled[sender] => move |x| {
+                            sender.input(AppInMsg::UpdateSplitSentences(x.is_active()))
+                        }
+                    },
+                    gtk::CheckButton::with_label("Refine the splits with silence detection (slow)") {
+                        #[watch]
+                        set_sensitive: model.split_sentences,
+                        connect_toggled[sender] => move |x| {
+                            sender.input(AppInMsg::UpdateRefineSplits(x.is_active()))
+                        }
+                    },
+                    gtk::CheckButton::with_label("Merge very short subtitles with their neighbours") {
+                        connect_toggled[sender] => move |x| {
+                            sender.input(AppInMsg::UpdateMergeFragments(x.is_active()))
+                        }
+                    },
+                },
+
+                gtk::Box {
+                    set_spacing: 5,
+                    set_margin_all: 5,
+                    set_orientation: gtk::Orientation::Horizontal,
+                    #[watch]
+                    set_sensitive: model.sensitive && model.merge_fragments,
+                    gtk::Label {
+                        set_label: "Short subtitles are under"
+                    },
+                        gtk::Box {
+                            set_orientation: gtk::Orientation::Vertical,
+                            relm4::gtk::SpinButton::builder()
+                            .adjustment(&Adjustment::new(MergeThresholds::default().min_duration as f64, 0.0, 10000.0, 50.0, 0.0, 0.0))
+                            .build(){
+                                connect_value_changed[sender] => move |x| {
+                                    sender.input(AppInMsg::UpdateMergeDuration(x.value()))
+                            }},
+                            gtk::Label {
+                                    set_label: "Duration (ms)"
+                                }
+                        },
+                        gtk::Box {
+                            set_orientation: gtk::Orientation::Vertical,
+                            relm4::gtk::SpinButton::builder()
+                            .adjustment(&Adjustment::new(MergeThresholds::default().min_chars as f64, 0.0, 100.0, 1.0, 0.0, 0.0))
+                            .build(){
+                                connect_value_changed[sender] => move |x| {
+                                    sender.input(AppInMsg::UpdateMergeChars(x.value()))
+                            }},
+                            gtk::Label {
+                                    set_label: "Letters"
+                                }
+                        },
+                    gtk::CheckButton::with_label("Move stray 「」 brackets to their subtitle") {
+                        set_active: MergeThresholds::default().unbalanced_brackets,
+                        connect_toggled[sender] => move |x| {
+                            sender.input(AppInMsg::UpdateMergeBrackets(x.is_active()))
+                        }
+                    },
+                },
+
+                gtk::Box {
+                    set_spacing: 5,
+                    set_margin_all: 5,
+                    set_orientation: gtk::Orientation::Horizontal,
+                    #[watch]
+                    set_sensitive: model.sensitive,
+                    gtk::Label {
+                        set_label: "Audio clips"
+                    },
+                    gtk::DropDown::from_strings(&["A folder per chapter", "Folders of a set number of clips", "All in game/audio"]) {
+                        connect_selected_notify[sender] => move |x| {
+                            sender.input(AppInMsg::UpdateClipLayout(x.selected()))
+                        }
+                    },
+                    relm4::gtk::SpinButton::builder()
+                    .adjustment(&Adjustment::new(1000.0, 1.0, 100000.0, 100.0, 0.0, 0.0))
+                    .build(){
+                        #[watch]
+                        set_sensitive: matches!(model.clip_layout, ClipLayout::Buckets(_)),
+                        connect_value_changed[sender] => move |x| {
+                            sender.input(AppInMsg::UpdateClipBucketSize(x.value()))
+                    }},
+                },
+
+                gtk::Box {
+                    set_spacing: 5,
+                    set_margin_all: 5,
+                    set_orientation: gtk::Orientation::Horizontal,
+                    #[watch]
+                    set_sensitive: model.sensitive,
+                    gtk::CheckButton::with_label("Pack audio and images into .rpa archives") {
+                        connect_toggled[sender] => move |x| {
+                            sender.input(AppInMsg::UpdatePack(x.is_active()))
+                        }
+                    },
+                    gtk::CheckButton::with_label("One archive per asset type") {
+                        set_active: true,
+                        #[watch]
+                        set_sensitive: model.pack,
+                        connect_toggled[sender] => move |x| {
+                            sender.input(AppInMsg::UpdatePackByType(x.is_active()))
+                        }
+                    },
+                    gtk::CheckButton::with_label("Remove the loose files") {
+                        #[watch]
+                        set_sensitive: model.pack,
+                        connect_toggled[sender] => move |x| {
+                            sender.input(AppInMsg::UpdatePackRemoveLoose(x.is_active()))
+                        }
+                    },
+                },
+
 
                 append = if model.show_button {
                     gtk::Button::with_label("Generate Deck !") {
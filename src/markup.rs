@@ -0,0 +1,170 @@
+use std::ops::Range;
+
+/// Ren'Py text tags put over a span of a line.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Markup {
+    /// `{rb}…{/rb}{rt}reading{/rt}`
+    Ruby(String),
+    /// Emphasis dots, a `{rt}﹅{/rt}` over every char.
+    Emphasis,
+    /// `{b}…{/b}`
+    Bold,
+    /// `{i}…{/i}`
+    Italic,
+    /// `{image=path}`, put between two chars: its span is empty.
+    Image(String),
+    /// Link to a note of the book, its marker in `{a=note:key}…{/a}` put between two chars
+    /// like an image.
+    NoteRef { key: String, marker: String },
+}
+
+impl Markup {
+    /// Markups put between two chars rather than over some.
+    fn is_point(&self) -> bool {
+        matches!(self, Markup::Image(_) | Markup::NoteRef { .. })
+    }
+
+    /// Tags that just wrap their text, and so can hold other spans.
+    fn wraps(&self) -> bool {
+        matches!(self, Markup::Bold | Markup::Italic)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Span {
+    /// Chars of the line covered.
+    pub range: Range<usize>,
+    pub markup: Markup,
+}
+
+/// Escapes text for a Ren'Py say statement: quotes, backslashes, newlines, and the `{`/`[`
+/// that would otherwise start a text tag or an interpolation.
+pub fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '{' => escaped.push_str("{{"),
+            '[' => escaped.push_str("[["),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Python string literal of `text`. Text `shown` to the player is escaped like a say statement,
+/// see [`escape`]; names and paths only get their quotes and backslashes escaped.
+pub fn python_string(text: &str, shown: bool) -> String {
+    let escaped = if shown {
+        escape(text)
+    } else {
+        text.replace('\\', "\\\\").replace('"', "\\\"")
+    };
+    format!("\"{escaped}\"")
+}
+
+/// Whether `inner` is inside `outer`. A point right at the end of `outer` comes after it.
+fn contains(outer: &Span, inner: &Span) -> bool {
+    if inner.range.is_empty() {
+        return outer.range.start <= inner.range.start && inner.range.start < outer.range.end;
+    }
+    outer.range.start <= inner.range.start && inner.range.end <= outer.range.end
+}
+
+/// Adds `span` to the line's spans, unless it overlaps one that's already there. Bold and
+/// italics may hold other spans, as long as they hold them whole.
+pub fn add_span(spans: &mut Vec<Span>, span: Span) -> bool {
+    if span.range.is_empty() != span.markup.is_point()
+        || spans.iter().any(|s| {
+            s.range.start < span.range.end
+                && span.range.start < s.range.end
+                && !(s.markup.wraps() && contains(s, &span)
+                    || span.markup.wraps() && contains(&span, s))
+        })
+    {
+        return false;
+    }
+    spans.push(span);
+    true
+}
+
+/// Renders `range` of the line, with the spans inside it. `spans` are sorted so that a span
+/// comes before the ones it holds.
+fn render_range(chars: &[char], range: Range<usize>, spans: &[&Span]) -> String {
+    let mut rendered = String::with_capacity(range.len() * 2);
+    let mut cursor = range.start;
+    let mut n = 0;
+    while n < spans.len() {
+        let span = spans[n];
+        // The spans held by this one come right after it.
+        let held = spans[n + 1..]
+            .iter()
+            .take_while(|s| contains(span, s))
+            .count();
+        let inner_spans = &spans[n + 1..n + 1 + held];
+        n += held + 1;
+        let span_range = span.range.start.min(range.end)..span.range.end.min(range.end);
+        if span_range.start < cursor {
+            continue;
+        }
+        rendered.push_str(&escape(
+            &chars[cursor..span_range.start].iter().collect::<String>(),
+        ));
+        let inner = render_range(chars, span_range.clone(), inner_spans);
+        match &span.markup {
+            Markup::Ruby(reading) => {
+                rendered.push_str(&format!(
+                    "{{rb}}{}{{/rb}}{{rt}}{}{{/rt}}",
+                    inner,
+                    escape(reading)
+                ));
+            }
+            Markup::Emphasis => {
+                for c in &chars[span_range.clone()] {
+                    if c.is_whitespace() {
+                        rendered.push_str(&escape(&c.to_string()));
+                    } else {
+                        rendered.push_str(&format!(
+                            "{{rb}}{}{{/rb}}{{rt}}﹅{{/rt}}",
+                            escape(&c.to_string())
+                        ));
+                    }
+                }
+            }
+            Markup::Bold => rendered.push_str(&format!("{{b}}{inner}{{/b}}")),
+            Markup::Italic => rendered.push_str(&format!("{{i}}{inner}{{/i}}")),
+            Markup::Image(path) => rendered.push_str(&format!("{{image={path}}}")),
+            Markup::NoteRef { key, marker } => rendered.push_str(&format!(
+                "{{a=note:{key}}}{{size=-8}}{}{{/size}}{{/a}}",
+                escape(marker)
+            )),
+        }
+        cursor = span_range.end;
+    }
+    rendered.push_str(&escape(
+        &chars[cursor..range.end].iter().collect::<String>(),
+    ));
+    rendered
+}
+
+/// The line as it goes in the script, escaped and with its spans turned into text tags.
+pub fn render(text: &str, spans: &[Span]) -> String {
+    let chars: Vec<char> = text.chars().collect();
+    let mut spans: Vec<&Span> = spans
+        .iter()
+        .filter(|s| s.range.start < chars.len() || s.range.is_empty())
+        .collect();
+    // Outer spans first: by start, images before what starts on the same char, then longest,
+    // then wrapping tags before what they hold.
+    spans.sort_by_key(|s| {
+        (
+            s.range.start,
+            !s.range.is_empty(),
+            std::cmp::Reverse(s.range.end),
+            !s.markup.wraps(),
+        )
+    });
+    render_range(&chars, 0..chars.len(), &spans)
+}
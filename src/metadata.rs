@@ -0,0 +1,134 @@
+use epub::doc::EpubDoc;
+use regex::{NoExpand, Regex};
+use std::io::{Read, Seek};
+use std::path::Path;
+
+use super::markup;
+
+/// `build.name` when the title has no ASCII words to make one from.
+const DEFAULT_BUILD_NAME: &str = "Audiobook";
+
+/// What the game is called and whose book it is, for `options.rpy`.
+#[derive(Debug, Clone, Default)]
+pub struct GameMetadata {
+    pub title: String,
+    pub author: Option<String>,
+    pub language: Option<String>,
+    /// The epub's unique identifier (an ISBN, an `urn:uuid:`…), which keeps the save folder the
+    /// same whatever the game is named.
+    pub identifier: Option<String>,
+}
+
+impl GameMetadata {
+    /// Metadata of a game with only a name.
+    pub fn named(title: &str) -> Self {
+        Self {
+            title: title.to_string(),
+            ..Default::default()
+        }
+    }
+
+    /// Metadata of the epub, `title` being used when the book has none.
+    pub fn from_epub<R: Read + Seek>(doc: &EpubDoc<R>, title: &str) -> Self {
+        let field = |name| doc.mdata(name).filter(|v| !v.trim().is_empty());
+        Self {
+            title: field("title").unwrap_or_else(|| title.to_string()),
+            author: field("creator"),
+            language: field("language"),
+            identifier: doc
+                .unique_identifier
+                .clone()
+                .or_else(|| field("identifier")),
+        }
+    }
+
+    /// ASCII name for the distributions, `build.name` not taking anything else.
+    pub fn build_name(&self) -> String {
+        let mut name = String::new();
+        for c in self.title.chars() {
+            if c.is_ascii_alphanumeric() {
+                name.push(c);
+            } else if !name.is_empty() && !name.ends_with('-') {
+                name.push('-');
+            }
+        }
+        let name = name.trim_end_matches('-');
+        if name.contains(|c: char| c.is_ascii_alphabetic()) {
+            name.to_string()
+        } else {
+            String::from(DEFAULT_BUILD_NAME)
+        }
+    }
+
+    /// Folder the saves go to, the build name and a hash of the identifier (of the title when
+    /// there's none) so two books never share one.
+    pub fn save_directory(&self) -> String {
+        let key = self.identifier.as_deref().unwrap_or(&self.title);
+        // FNV-1a, which stays the same from one build of the converter to the next.
+        let hash = key.bytes().fold(0x811c9dc5u32, |hash, b| {
+            (hash ^ b as u32).wrapping_mul(0x01000193)
+        });
+        format!("{}-{hash:08x}", self.build_name())
+    }
+
+    /// Text of the about screen, a paragraph for each field.
+    fn about(&self) -> String {
+        let mut about = vec![self.title.clone()];
+        if let Some(author) = &self.author {
+            about.push(author.clone());
+        }
+        if let Some(language) = &self.language {
+            about.push(format!("Language: {language}"));
+        }
+        if let Some(identifier) = &self.identifier {
+            about.push(format!("Identifier: {identifier}"));
+        }
+        about.join("\n\n")
+    }
+}
+
+/// Replaces the define matched by `pattern`, or adds `define` at the end when there's none.
+fn set_define(options: &mut String, pattern: &str, define: &str) {
+    let regex = Regex::new(pattern).unwrap();
+    if regex.is_match(options) {
+        *options = regex.replace(options, NoExpand(define)).into_owned();
+    } else {
+        options.push_str(&format!("\n{define}\n"));
+    }
+}
+
+/// Writes the game's name, build name, save folder and about text in `options.rpy`.
+pub fn write_options(game_folder: &Path, metadata: &GameMetadata) -> std::io::Result<()> {
+    let path = game_folder.join("options.rpy");
+    let mut options = std::fs::read_to_string(&path)?;
+    set_define(
+        &mut options,
+        r"(?m)^define config\.name = .*$",
+        &format!(
+            "define config.name = _({})",
+            markup::python_string(&metadata.title, true)
+        ),
+    );
+    set_define(
+        &mut options,
+        r"(?m)^define build\.name = .*$",
+        &format!("define build.name = \"{}\"", metadata.build_name()),
+    );
+    set_define(
+        &mut options,
+        r"(?m)^define config\.save_directory = .*$",
+        &format!(
+            "define config.save_directory = \"{}\"",
+            metadata.save_directory()
+        ),
+    );
+    set_define(
+        &mut options,
+        r#"(?ms)^define gui\.about = _p\(""".*?"""\)"#,
+        &format!(
+            "define gui.about = _p(\"\"\"\n{}\n\"\"\")",
+            markup::escape(&metadata.about())
+        ),
+    );
+    std::fs::write(path, options)
+}
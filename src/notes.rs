@@ -0,0 +1,162 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::path::Path;
+
+use super::align::LineAlignment;
+use super::epub_text::EpubText;
+use super::gallery::add_navigation_button;
+use super::markup::{self, Markup, Span};
+
+/// A note linked from the script, under the chapter its link is in.
+#[derive(Debug, Clone)]
+pub struct PlacedNote {
+    /// Name of the note in the game, what the `{a=note:…}` links point to.
+    pub key: String,
+    pub marker: String,
+    pub text: String,
+    pub chapter: Option<String>,
+}
+
+/// Puts a link on the line where each note reference of the book went. Returns the notes linked,
+/// once each, in the order of their first link, and how many links couldn't be placed.
+pub fn place_notes(
+    alignment: &LineAlignment,
+    book: &EpubText,
+    spans: &mut [Vec<Span>],
+) -> (Vec<PlacedNote>, usize) {
+    let texts: HashMap<&str, &str> = book
+        .notes
+        .iter()
+        .map(|n| (n.target.as_str(), n.text.as_str()))
+        .collect();
+    let mut keys: HashMap<&str, String> = HashMap::new();
+    let mut placed = vec![];
+    let mut missed = 0;
+    for note_ref in &book.note_refs {
+        let Some(text) = texts.get(note_ref.target.as_str()) else {
+            missed += 1;
+            continue;
+        };
+        let Some((line, position)) = alignment.map_point(note_ref.position) else {
+            missed += 1;
+            continue;
+        };
+        let key = match keys.get(note_ref.target.as_str()) {
+            Some(key) => key.clone(),
+            None => {
+                let key = format!("n{}", placed.len());
+                placed.push(PlacedNote {
+                    key: key.clone(),
+                    marker: note_ref.marker.clone(),
+                    text: text.to_string(),
+                    chapter: book.chapter_titles.get(note_ref.spine).cloned().flatten(),
+                });
+                keys.insert(&note_ref.target, key.clone());
+                key
+            }
+        };
+        let marker = match note_ref.marker.as_str() {
+            "" => String::from("*"),
+            marker => marker.to_string(),
+        };
+        if !markup::add_span(
+            &mut spans[line],
+            Span {
+                range: position..position,
+                markup: Markup::NoteRef { key, marker },
+            },
+        ) {
+            missed += 1;
+        }
+    }
+    (placed, missed)
+}
+
+/// Writes `notes.rpy`: the notes, the popup their links open, and an index of them by chapter
+/// with a button in the game menu's navigation. Returns whether the button could be added.
+pub fn write_notes(game_folder: &Path, notes: &[PlacedNote]) -> bool {
+    let mut script = String::from("define book_notes = {\n");
+    for note in notes {
+        writeln!(
+            script,
+            "    \"{}\": {},",
+            note.key,
+            markup::python_string(&note.text, true)
+        )
+        .unwrap();
+    }
+    script.push_str("}\n\ndefine book_note_chapters = [\n");
+    let mut chapters: Vec<(&Option<String>, Vec<&PlacedNote>)> = vec![];
+    for note in notes {
+        match chapters.last_mut() {
+            Some((chapter, notes)) if *chapter == &note.chapter => notes.push(note),
+            _ => chapters.push((&note.chapter, vec![note])),
+        }
+    }
+    for (chapter, notes) in chapters {
+        let notes: Vec<String> = notes
+            .iter()
+            .map(|n| {
+                format!(
+                    "({}, \"{}\")",
+                    markup::python_string(&n.marker, true),
+                    n.key
+                )
+            })
+            .collect();
+        writeln!(
+            script,
+            "    ({}, [{}]),",
+            chapter
+                .as_deref()
+                .map_or(String::from("None"), |c| markup::python_string(c, true)),
+            notes.join(", ")
+        )
+        .unwrap();
+    }
+    script.push_str(
+        "]
+
+init python:
+    def show_book_note(note):
+        renpy.show_screen(\"book_note\", note=note)
+        renpy.restart_interaction()
+
+    config.hyperlink_handlers[\"note\"] = show_book_note
+
+screen book_note(note):
+    modal True
+    zorder 200
+    key \"game_menu\" action Hide(\"book_note\")
+    button:
+        xfill True
+        yfill True
+        action Hide(\"book_note\")
+    frame:
+        xalign 0.5
+        yalign 0.5
+        xmaximum 1200
+        padding (40, 30)
+        vbox:
+            spacing 20
+            text book_notes[note]
+            textbutton _(\"Close\") action Hide(\"book_note\") xalign 1.0
+
+screen book_notes_index():
+    tag menu
+    use game_menu(_(\"Notes\"), scroll=\"viewport\"):
+        vbox:
+            spacing 10
+            for chapter, chapter_notes in book_note_chapters:
+                if chapter:
+                    label chapter
+                for marker, note in chapter_notes:
+                    hbox:
+                        spacing 10
+                        text marker size 20 min_width 40
+                        text book_notes[note] size 20
+",
+    );
+    std::fs::write(game_folder.join("notes.rpy"), script).unwrap();
+    add_navigation_button(game_folder, "Notes", "book_notes_index")
+}
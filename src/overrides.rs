@@ -0,0 +1,227 @@
+use std::collections::HashMap;
+use std::io;
+use std::ops::{Range, RangeInclusive};
+use std::path::Path;
+
+use super::epub_text::Ruby;
+use super::markup::{self, Markup, Span};
+
+/// What an override does to the rubies of its base.
+#[derive(Debug, Clone, PartialEq)]
+pub enum OverrideAction {
+    /// Gives every occurrence this reading, replacing the ruby it had if any.
+    Reading(String),
+    /// Like `Reading`, but also wins over rubies that only partly cover the base (names).
+    Forced(String),
+    /// Removes the rubies of the base, or only the ones with this reading.
+    Suppress(Option<String>),
+}
+
+/// Where an override applies, in the numbering shown to the user (both start at 1).
+#[derive(Debug, Clone, PartialEq)]
+pub enum OverrideScope {
+    Everywhere,
+    Lines(RangeInclusive<usize>),
+    Chapter(usize),
+}
+
+impl OverrideScope {
+    /// Whether line `line` (from 0) is in the scope. A line that isn't known is only in scope
+    /// everywhere.
+    fn contains(&self, line: Option<usize>, chapters: &[usize]) -> bool {
+        match (self, line) {
+            (OverrideScope::Everywhere, _) => true,
+            (OverrideScope::Lines(range), Some(line)) => range.contains(&(line + 1)),
+            (OverrideScope::Chapter(chapter), Some(line)) => {
+                chapters.get(line).map(|c| c + 1) == Some(*chapter)
+            }
+            (_, None) => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Override {
+    pub base: String,
+    pub action: OverrideAction,
+    pub scope: OverrideScope,
+}
+
+/// Furigana fixes kept by the user next to the project, so they survive regenerating the script.
+///
+/// One override per line, tab separated: `base`, `reading`, and optionally a scope
+/// (`line 12`, `lines 12-40` or `chapter 3`). A reading of `-` suppresses the base's rubies,
+/// `-reading` only the ones reading that way, and `!reading` forces the reading over anything
+/// else (for names). Lines starting with `#` are comments.
+#[derive(Debug, Clone, Default)]
+pub struct Overrides {
+    pub entries: Vec<Override>,
+    /// Why the lines of the file that were ignored were, to show the user.
+    pub warnings: Vec<String>,
+}
+
+fn parse_scope(scope: &str) -> Option<OverrideScope> {
+    let (kind, value) = scope.trim().split_once(char::is_whitespace)?;
+    let value = value.trim();
+    match kind {
+        "line" => {
+            let line = value.parse().ok()?;
+            Some(OverrideScope::Lines(line..=line))
+        }
+        "lines" => {
+            let (start, end) = value.split_once('-')?;
+            Some(OverrideScope::Lines(
+                start.trim().parse().ok()?..=end.trim().parse().ok()?,
+            ))
+        }
+        "chapter" => Some(OverrideScope::Chapter(value.parse().ok()?)),
+        _ => None,
+    }
+}
+
+/// Overrides by the first char of their base, to find every base of a line in one pass.
+fn index_by_first_char(entries: &[Override]) -> HashMap<char, Vec<usize>> {
+    let mut index: HashMap<char, Vec<usize>> = HashMap::new();
+    for (n, o) in entries.iter().enumerate() {
+        if let Some(first) = o.base.chars().next() {
+            index.entry(first).or_default().push(n);
+        }
+    }
+    index
+}
+
+fn apply_one(o: &Override, range: Range<usize>, spans: &mut Vec<Span>) {
+    let inside = |s: &Span| range.start <= s.range.start && s.range.end <= range.end;
+    let overlaps = |s: &Span| s.range.start < range.end && range.start < s.range.end;
+    match &o.action {
+        OverrideAction::Suppress(reading) => spans.retain(|s| {
+            !(inside(s)
+                && match (&s.markup, reading) {
+                    (Markup::Ruby(_), None) => true,
+                    (Markup::Ruby(r), Some(reading)) => r == reading,
+                    _ => false,
+                })
+        }),
+        OverrideAction::Reading(reading) | OverrideAction::Forced(reading) => {
+            let forced = matches!(o.action, OverrideAction::Forced(_));
+            spans.retain(|s| {
+                !(matches!(s.markup, Markup::Ruby(_) | Markup::Emphasis)
+                    && (inside(s) || forced && overlaps(s)))
+            });
+            markup::add_span(
+                spans,
+                Span {
+                    range: range.clone(),
+                    markup: Markup::Ruby(reading.clone()),
+                },
+            );
+        }
+    }
+}
+
+impl Overrides {
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let mut overrides = Self::default();
+        for (n, line) in std::fs::read_to_string(path)?.lines().enumerate() {
+            if line.trim().is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut fields = line.split('\t');
+            let (Some(base), Some(reading)) = (fields.next(), fields.next()) else {
+                overrides.warnings.push(format!(
+                    "Ignoring furigana override on line {}: no reading",
+                    n + 1
+                ));
+                continue;
+            };
+            let scope = match fields.next() {
+                Some(scope) if !scope.trim().is_empty() => match parse_scope(scope) {
+                    Some(scope) => scope,
+                    None => {
+                        overrides.warnings.push(format!(
+                            "Ignoring furigana override on line {}: bad scope",
+                            n + 1
+                        ));
+                        continue;
+                    }
+                },
+                _ => OverrideScope::Everywhere,
+            };
+            let reading = reading.trim();
+            let action = if let Some(reading) = reading.strip_prefix('-') {
+                OverrideAction::Suppress((!reading.is_empty()).then(|| reading.to_string()))
+            } else if let Some(reading) = reading.strip_prefix('!') {
+                OverrideAction::Forced(reading.to_string())
+            } else {
+                OverrideAction::Reading(reading.to_string())
+            };
+            let base = base.trim();
+            if base.is_empty() {
+                continue;
+            }
+            overrides.entries.push(Override {
+                base: base.to_string(),
+                action,
+                scope,
+            });
+        }
+        Ok(overrides)
+    }
+
+    /// Drops the book's rubies that an unscoped override replaces or suppresses anyway, before
+    /// they get placed, so they aren't reported as failures.
+    pub fn filter_book_rubies(&self, rubies: &mut Vec<Ruby>) {
+        rubies.retain(|ruby| {
+            !self.entries.iter().any(|o| {
+                o.scope == OverrideScope::Everywhere
+                    && o.base == ruby.base
+                    && match &o.action {
+                        OverrideAction::Forced(_) => true,
+                        OverrideAction::Suppress(reading) => {
+                            reading.as_ref().is_none_or(|r| *r == ruby.reading)
+                        }
+                        OverrideAction::Reading(_) => false,
+                    }
+            })
+        });
+    }
+
+    /// Whether an override takes care of a book ruby that couldn't be placed, at the line it
+    /// should have gone to if known: it gives the base its own reading or suppresses this one.
+    pub fn settles(&self, ruby: &Ruby, line: Option<usize>, chapters: &[usize]) -> bool {
+        self.entries.iter().any(|o| {
+            o.base == ruby.base
+                && o.scope.contains(line, chapters)
+                && match &o.action {
+                    OverrideAction::Reading(_) | OverrideAction::Forced(_) => true,
+                    OverrideAction::Suppress(reading) => {
+                        reading.as_ref().is_none_or(|r| *r == ruby.reading)
+                    }
+                }
+        })
+    }
+
+    /// Applies the overrides to the spans of every line, once the epub and the dictionary have
+    /// placed their rubies. Each line is scanned once for all the bases, and the overrides found
+    /// in it are applied in file order.
+    pub fn apply(&self, lines: &[String], chapters: &[usize], spans: &mut [Vec<Span>]) {
+        let index = index_by_first_char(&self.entries);
+        for (i, line) in lines.iter().enumerate() {
+            let mut found: Vec<(usize, Range<usize>)> = vec![];
+            for (start, (byte, c)) in line.char_indices().enumerate() {
+                for n in index.get(&c).into_iter().flatten() {
+                    let o = &self.entries[*n];
+                    if line[byte..].starts_with(&o.base) {
+                        found.push((*n, start..start + o.base.chars().count()));
+                    }
+                }
+            }
+            found.retain(|(n, _)| self.entries[*n].scope.contains(Some(i), chapters));
+            // Stable, so occurrences of one override stay in line order.
+            found.sort_by_key(|(n, _)| *n);
+            for (n, range) in found {
+                apply_one(&self.entries[n], range, &mut spans[i]);
+            }
+        }
+    }
+}
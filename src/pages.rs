@@ -0,0 +1,117 @@
+use std::path::Path;
+
+use super::epub_text::{BookImage, PageSpread};
+
+/// Keeps the first image of each page of the book, which is the page itself, and every image of
+/// the other spine items.
+pub fn page_images(images: &[BookImage], pages: &[bool]) -> Vec<BookImage> {
+    let mut seen = vec![false; pages.len()];
+    images
+        .iter()
+        .filter(|image| {
+            if image.gaiji || !pages.get(image.spine).copied().unwrap_or(false) {
+                return true;
+            }
+            !std::mem::replace(&mut seen[image.spine], true)
+        })
+        .cloned()
+        .collect()
+}
+
+/// Pairs the pages of `pages`, in reading order, into the spreads of the book. Each page goes on
+/// the side of the spread its itemref says, the others after the page before them: the first page
+/// of a spread on the right when the book reads right to left, on the left otherwise. A page
+/// without the other side of its spread is centred.
+pub fn pair_pages<T: Clone>(
+    pages: &[(T, Option<PageSpread>)],
+    right_to_left: bool,
+) -> Vec<Vec<(T, PageSpread)>> {
+    let (first, second) = if right_to_left {
+        (PageSpread::Right, PageSpread::Left)
+    } else {
+        (PageSpread::Left, PageSpread::Right)
+    };
+    let mut spreads: Vec<Vec<(T, PageSpread)>> = vec![];
+    // Page waiting for the second side of its spread.
+    let mut open: Option<&T> = None;
+    for (page, spread) in pages {
+        let side = spread.unwrap_or(match open {
+            Some(_) => second,
+            None => first,
+        });
+        if side == second {
+            if let Some(open) = open.take() {
+                spreads.push(vec![(open.clone(), first), (page.clone(), second)]);
+                continue;
+            }
+        }
+        spreads.extend(
+            open.take()
+                .map(|open| vec![(open.clone(), PageSpread::Center)]),
+        );
+        if side == first {
+            open = Some(page);
+        } else {
+            spreads.push(vec![(page.clone(), PageSpread::Center)]);
+        }
+    }
+    spreads.extend(open.map(|open| vec![(open.clone(), PageSpread::Center)]));
+    spreads
+}
+
+/// Size to export a page at for the game's `size`: half its width on a side of a spread, all of
+/// it when shown alone.
+pub fn page_size(side: PageSpread, size: (u32, u32)) -> (u32, u32) {
+    match side {
+        PageSpread::Left | PageSpread::Right => (size.0 / 2, size.1),
+        PageSpread::Center => size,
+    }
+}
+
+/// Script showing `spreads` of pages exported to files, one after the other.
+pub fn render_spreads(spreads: &[Vec<(String, PageSpread)>], transition: &str) -> String {
+    let mut block = String::from("    window hide\n    nvl hide\n");
+    for spread in spreads {
+        block.push_str("    scene black\n");
+        for (file, side) in spread {
+            // Files of `images/` are defined as images by their name.
+            let name = Path::new(file).file_stem().unwrap_or_default();
+            let xalign = match side {
+                PageSpread::Left => "0.0",
+                PageSpread::Right => "1.0",
+                PageSpread::Center => "0.5",
+            };
+            block.push_str(&format!(
+                "    show {}:\n        xalign {xalign} yalign 0.5\n",
+                name.to_string_lossy()
+            ));
+        }
+        block.push_str(&format!("    with {transition}\n    pause\n"));
+    }
+    block.push_str("    window show\n");
+    block
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn single_page_is_centred_at_full_width() {
+        let spreads = pair_pages(&[("page", None)], true);
+        assert_eq!(spreads, vec![vec![("page", PageSpread::Center)]]);
+        assert_eq!(page_size(spreads[0][0].1, (1920, 1080)), (1920, 1080));
+    }
+
+    #[test]
+    fn paired_pages_get_half_the_width() {
+        let spreads = pair_pages(&[("a", None), ("b", None)], true);
+        assert_eq!(
+            spreads,
+            vec![vec![("a", PageSpread::Right), ("b", PageSpread::Left)]]
+        );
+        for (_, side) in &spreads[0] {
+            assert_eq!(page_size(*side, (1920, 1080)), (960, 1080));
+        }
+    }
+}
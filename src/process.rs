@@ -1,25 +1,67 @@
+use align::LineAlignment;
 use epub::doc::EpubDoc;
+use epub_text::{EpubText, PageSpread, Ruby, TextStyle};
 use getch::Getch;
 use itertools::Itertools;
+use markup::{Markup, Span};
 use rayon::prelude::{ParallelBridge, ParallelIterator};
-use scraper::{Element, Selector};
+use regex::Regex;
+use ruby_report::RubyFailure;
 use srtlib::{Subtitle, Subtitles, Timestamp};
 use std::sync::mpsc::Sender;
 use std::{
-    collections::VecDeque,
+    collections::HashMap,
     fmt::Write,
     fs::File,
-    path::PathBuf,
+    io,
+    ops::Range,
+    path::{Path, PathBuf},
     process::Command,
     sync::{
         atomic::{AtomicBool, AtomicUsize, Ordering},
-        Arc,
+        Arc, Mutex,
     },
 };
 
+// Submodules live next to this file since it's also the library root.
+#[path = "align.rs"]
+pub mod align;
+#[path = "ass.rs"]
+pub mod ass;
+#[path = "cues.rs"]
+pub mod cues;
+#[path = "dictionary.rs"]
+pub mod dictionary;
+#[path = "epub_text.rs"]
+pub mod epub_text;
+#[path = "gaiji.rs"]
+pub mod gaiji;
+#[path = "gallery.rs"]
+pub mod gallery;
+#[path = "illustrations.rs"]
+pub mod illustrations;
+#[path = "inline_ruby.rs"]
+pub mod inline_ruby;
+#[path = "markup.rs"]
+pub mod markup;
+#[path = "metadata.rs"]
+pub mod metadata;
+#[path = "notes.rs"]
+pub mod notes;
+#[path = "overrides.rs"]
+pub mod overrides;
+#[path = "pages.rs"]
+pub mod pages;
+#[path = "rpa.rs"]
+pub mod rpa;
+#[path = "ruby_report.rs"]
+pub mod ruby_report;
+
 const CHUNK_SIZE: usize = 25;
 // const SILENCE_OGG: &[u8] = include_bytes!("../silence.ogg");
 const SILENCE_MP3: &[u8] = include_bytes!("../silence.mp3");
+/// Containers Ren'Py can seek into with a `<from .. to ..>` prefix, anything else gets transcoded.
+const SEEKABLE_EXTENSIONS: [&str; 5] = ["mp3", "ogg", "opus", "flac", "wav"];
 
 fn timestamp_to_str(t: Timestamp) -> String {
     let (hours, mins, secs, millis) = t.get();
@@ -35,71 +77,184 @@ fn subtime_to_renpy(s: &Subtitle) -> String {
     )
 }
 
-fn replace_rubies(text: &mut String, rubies: &mut VecDeque<[String; 3]>) {
-    if rubies.front().is_none() {
-        return;
+/// Share of a line's letters, and of the letters of the book they were matched over, that have to
+/// be matched for the book's wording to be used.
+const MIN_LINE_CONFIDENCE: f32 = 0.8;
+
+/// Replaces each line confidently matched with the book by the book's own wording, keeping the
+/// punctuation around it, and returns the (index, confidence) of the lines left as they were.
+fn use_book_wording(
+    lines: &mut [String],
+    book: &EpubText,
+    alignment: &LineAlignment,
+) -> Vec<(usize, f32)> {
+    let book_chars: Vec<char> = book.text.chars().collect();
+    let mut unaligned = vec![];
+    let mut spans: Vec<Option<Range<usize>>> = Vec::with_capacity(lines.len());
+    let mut previous_end = 0;
+    for (i, (span, confidence)) in alignment.line_matches().into_iter().enumerate() {
+        match span {
+            Some(span) if confidence >= MIN_LINE_CONFIDENCE && span.end > previous_end => {
+                let span = span.start.max(previous_end)..span.end;
+                previous_end = span.end;
+                spans.push(Some(span));
+            }
+            _ => {
+                unaligned.push((i, confidence));
+                spans.push(None);
+            }
+        }
     }
-    let mut queue: Vec<[String; 2]> = Vec::with_capacity(5);
-    while textdistance::str::overlap(&rubies[0][2], text) > 0.5 && text.contains(&rubies[0][0]) {
-        let front = rubies.pop_front().unwrap();
-        queue.push([front[0].clone(), front[1].clone()]);
-        if rubies.is_empty() {
-            break;
+
+    // Trailing punctuation goes with the line before it, what's left before a line (opening
+    // quotes mostly) with that line.
+    let is_punctuation = |c: char| !c.is_alphanumeric() && !c.is_whitespace();
+    let mut limit = book_chars.len();
+    for span in spans.iter_mut().rev().flatten() {
+        let start = span.start;
+        while span.end < limit
+            && is_punctuation(book_chars[span.end])
+            && !cues::OPENING_BRACKETS.contains(&book_chars[span.end])
+        {
+            span.end += 1;
         }
+        limit = start;
     }
-    while let Some(replacement) = queue.pop() {
-        *text = text.replace(
-            &replacement[0].to_string(),
-            &format!(
-                "{{rb}}{}{{/rb}}{{rt}}{}{{/rt}}",
-                replacement[0], replacement[1]
-            ),
-        );
+    let mut previous_end = 0;
+    for span in spans.iter_mut().flatten() {
+        while span.start > previous_end && is_punctuation(book_chars[span.start - 1]) {
+            span.start -= 1;
+        }
+        previous_end = span.end;
     }
+
+    for (line, span) in lines.iter_mut().zip(spans) {
+        if let Some(span) = span {
+            *line = book_chars[span]
+                .iter()
+                .collect::<String>()
+                .trim()
+                .to_string();
+        }
+    }
+    unaligned
 }
 
-fn _replace_rubies_old(text: &mut String, rubies: &mut VecDeque<[String; 3]>) {
-    if rubies.front().is_none() {
-        return;
+/// Writes the lines left with the subtitle's text to `unaligned_lines.txt` next to the game, or
+/// removes the file of an earlier run when every line was matched.
+fn write_unaligned_report(
+    game_folder: &Path,
+    lines: &[String],
+    unaligned: &[(usize, f32)],
+) -> io::Result<()> {
+    let mut path = game_folder.to_path_buf();
+    path.pop();
+    path.push("unaligned_lines.txt");
+    if unaligned.is_empty() {
+        let _ = std::fs::remove_file(path);
+        return Ok(());
     }
-    let mut queue: Vec<[String; 2]> = Vec::with_capacity(5);
-    while textdistance::str::overlap(&rubies[0][2], text) > 0.5
-        && text.contains(&format!("{}{}", &rubies[0][0], &rubies[0][1]))
-    {
-        let front = rubies.pop_front().unwrap();
-        queue.push([front[0].clone(), front[1].clone()]);
-        if rubies.is_empty() {
-            break;
+    let report: String = unaligned
+        .iter()
+        .map(|(i, confidence)| format!("{}\t{:.0}%\t{}\n", i + 1, confidence * 100.0, lines[*i]))
+        .collect();
+    std::fs::write(path, report)
+}
+
+/// Puts each ruby of the book on the exact chars of the line the alignment mapped it to,
+/// returning the rubies that couldn't be placed.
+pub fn place_rubies(
+    alignment: &LineAlignment,
+    book: &EpubText,
+    spans: &mut [Vec<Span>],
+) -> Vec<Ruby> {
+    let mut unplaced = vec![];
+    for ruby in &book.rubies {
+        let placed = alignment
+            .map_span(ruby.span.clone())
+            .is_some_and(|(line, range)| {
+                markup::add_span(
+                    &mut spans[line],
+                    Span {
+                        range,
+                        markup: Markup::Ruby(ruby.reading.clone()),
+                    },
+                )
+            });
+        if !placed {
+            unplaced.push(ruby.clone());
         }
     }
-    while let Some(replacement) = queue.pop() {
-        *text = text.replace(
-            &format!("{}{}", replacement[0], replacement[1]),
-            &format!(
-                "{{rb}}{}{{/rb}}{{rt}}{}{{/rt}}",
-                replacement[0], replacement[1]
-            ),
-        );
+    unplaced
+}
+
+/// Puts the book's emphasis dots, bold and italics on the lines its text went to. Styles are
+/// laid under the rubies, which win where both can't fit.
+fn place_styles(alignment: &LineAlignment, book: &EpubText, spans: &mut [Vec<Span>]) {
+    for styled in &book.styles {
+        let markup = match styled.style {
+            TextStyle::Emphasis => Markup::Emphasis,
+            TextStyle::Bold => Markup::Bold,
+            TextStyle::Italic => Markup::Italic,
+        };
+        for (line, range) in alignment.map_span_pieces(styled.span.clone()) {
+            markup::add_span(
+                &mut spans[line],
+                Span {
+                    range,
+                    markup: markup.clone(),
+                },
+            );
+        }
+    }
+}
+
+/// Puts each gaiji of the book, as an inline image, where its spot in the text went.
+fn place_gaiji(
+    alignment: &LineAlignment,
+    book: &EpubText,
+    gaiji: &HashMap<PathBuf, String>,
+    spans: &mut [Vec<Span>],
+) {
+    for image in book.images.iter().filter(|i| i.gaiji) {
+        let Some(path) = gaiji.get(&image.path) else {
+            continue;
+        };
+        if let Some((line, position)) = alignment.map_point(image.position) {
+            markup::add_span(
+                &mut spans[line],
+                Span {
+                    range: position..position,
+                    markup: Markup::Image(path.clone()),
+                },
+            );
+        }
     }
 }
 
+/// ffmpeg arguments cutting the clips of `s` a previous run didn't already cut with the same
+/// timing, and the indices of the clips (re)made, silent ones being written right away. A clip
+/// that's there but not in the manifest is taken as cut.
 fn prepare_ffmpeg_command(
-    start: usize,
-    count: usize,
     s: &[Subtitle],
-    game_folder: &str,
-) -> Vec<String> {
-    let mut r = Vec::with_capacity(count * 10);
-    println!("{}", s.len());
-    //TODO off by one here?
-    for i in 0..count {
-        let n = start + i;
-        let path_str = format!("{}/audio/audiobook-{n}.mp3", game_folder);
-        let path = PathBuf::from(&path_str);
-        if path.exists() {
+    clips: &[String],
+    audio_folder: &Path,
+    manifest: &HashMap<String, String>,
+) -> (Vec<String>, Vec<usize>) {
+    let mut r = Vec::with_capacity(clips.len() * 10);
+    let mut made = vec![];
+    for (i, (sub, clip)) in s.iter().zip(clips).enumerate() {
+        let mut path = audio_folder.to_path_buf();
+        path.push(clip);
+        if path.exists()
+            && manifest
+                .get(clip)
+                .is_none_or(|timing| *timing == manifest_timing(sub))
+        {
             continue;
         }
-        if s[i].start_time >= s[i].end_time {
+        made.push(i);
+        if sub.start_time >= sub.end_time {
             std::fs::write(&path, SILENCE_MP3).unwrap();
             continue;
         }
@@ -108,15 +263,276 @@ fn prepare_ffmpeg_command(
                 "-c",
                 "copy",
                 "-ss",
-                &s[i].start_time.to_string().replace(',', "."),
+                &sub.start_time.to_string().replace(',', "."),
                 "-to",
-                &s[i].end_time.to_string().replace(',', "."),
-                &path_str,
+                &sub.end_time.to_string().replace(',', "."),
+                &path.to_string_lossy(),
             ]
             .map(|s| s.to_string()),
         )
     }
-    r
+    (r, made)
+}
+
+/// How the split clips are arranged inside `game/audio/`.
+#[derive(Debug, Clone, Copy)]
+pub enum ClipLayout {
+    /// Everything directly in `game/audio/`, as `audiobook-{n}.mp3`.
+    Flat,
+    /// Folders holding at most that many clips each.
+    Buckets(usize),
+    /// One folder per chapter marker of the audiobook, falling back to buckets of
+    /// `CHAPTERLESS_BUCKET` clips when the file has no chapters.
+    Chapters,
+}
+
+const CHAPTERLESS_BUCKET: usize = 1000;
+const CLIP_MANIFEST: &str = "clips.tsv";
+/// What the embedded audiobook was made from, kept next to it in `game/audio/`.
+const AUDIOBOOK_SOURCE: &str = "audiobook.source";
+
+/// Chapter start times of the audiobook in milliseconds, as reported by ffprobe.
+fn audiobook_chapters(audiobook: &Path) -> Vec<u32> {
+    let output = ffprobe_command()
+        .args([
+            "-v",
+            "error",
+            "-show_entries",
+            "chapter=start_time",
+            "-of",
+            "csv=p=0",
+            &audiobook.to_string_lossy(),
+        ])
+        .output();
+    match output {
+        Ok(output) => String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .filter_map(|l| l.trim().parse::<f64>().ok())
+            .map(|secs| (secs * 1000.0) as u32)
+            .collect(),
+        Err(_) => vec![],
+    }
+}
+
+fn timestamp_to_millis(t: Timestamp) -> u32 {
+    let (hours, mins, secs, millis) = t.get();
+    Timestamp::convert_to_milliseconds(hours, mins, secs, millis)
+}
+
+/// Chapter index of every line, 0 for everything when the audiobook has no chapter markers.
+fn line_chapters(subs: &[Subtitle], chapter_starts: &[u32]) -> Vec<usize> {
+    subs.iter()
+        .map(|s| {
+            let start = timestamp_to_millis(s.start_time);
+            chapter_starts
+                .iter()
+                .filter(|c| **c <= start)
+                .count()
+                .saturating_sub(1)
+        })
+        .collect()
+}
+
+/// Clip path of every line, relative to `game/audio/`.
+fn clip_paths(layout: ClipLayout, chapters: &[usize], has_markers: bool) -> Vec<String> {
+    let mut index_in_chapter = 0;
+    chapters
+        .iter()
+        .enumerate()
+        .map(|(n, chapter)| match layout {
+            ClipLayout::Flat => format!("audiobook-{n}.mp3"),
+            ClipLayout::Chapters if has_markers => {
+                if n > 0 && chapters[n - 1] != *chapter {
+                    index_in_chapter = 0;
+                }
+                index_in_chapter += 1;
+                format!(
+                    "ch{:03}/ch{:03}-{:05}.mp3",
+                    chapter + 1,
+                    chapter + 1,
+                    index_in_chapter
+                )
+            }
+            ClipLayout::Chapters | ClipLayout::Buckets(_) => {
+                let size = match layout {
+                    ClipLayout::Buckets(size) => size.max(1),
+                    _ => CHAPTERLESS_BUCKET,
+                };
+                format!(
+                    "part{:03}/part{:03}-{:05}.mp3",
+                    n / size + 1,
+                    n / size + 1,
+                    n % size + 1
+                )
+            }
+        })
+        .collect()
+}
+
+fn manifest_timing(s: &Subtitle) -> String {
+    format!("{}\t{}", s.start_time, s.end_time)
+}
+
+/// Reads the clip manifest of a previous run, mapping each clip to the timing it was cut with.
+fn read_clip_manifest(audio_folder: &Path) -> HashMap<String, String> {
+    let mut path = audio_folder.to_path_buf();
+    path.push(CLIP_MANIFEST);
+    std::fs::read_to_string(path)
+        .unwrap_or_default()
+        .lines()
+        .filter_map(|l| l.split_once('\t'))
+        .map(|(clip, timing)| (clip.to_string(), timing.to_string()))
+        .collect()
+}
+
+/// Removes the clips of a previous run that aren't part of this one, such as those of another
+/// layout, and rewrites the manifest with only the clips still there.
+fn remove_stale_clips(
+    audio_folder: &Path,
+    clips: &[String],
+    layout: ClipLayout,
+    manifest: &mut HashMap<String, String>,
+) {
+    let current: std::collections::HashSet<&String> = clips.iter().collect();
+    let mut stale: Vec<PathBuf> = manifest
+        .keys()
+        .filter(|clip| !current.contains(clip))
+        .map(|clip| audio_folder.join(clip))
+        .collect();
+    // Runs from before the manifest only left flat clips.
+    if !matches!(layout, ClipLayout::Flat) {
+        let flat = Regex::new(r"^audiobook-\d+\.mp3$").unwrap();
+        stale.extend(
+            std::fs::read_dir(audio_folder)
+                .into_iter()
+                .flatten()
+                .flatten()
+                .filter(|entry| flat.is_match(&entry.file_name().to_string_lossy()))
+                .map(|entry| entry.path()),
+        );
+    }
+    for path in stale {
+        let _ = std::fs::remove_file(&path);
+        // Only goes when it's left empty.
+        if let Some(folder) = path.parent().filter(|f| *f != audio_folder) {
+            let _ = std::fs::remove_dir(folder);
+        }
+    }
+    manifest.retain(|clip, _| current.contains(clip));
+    let mut contents = String::new();
+    for (clip, timing) in manifest.iter() {
+        writeln!(contents, "{clip}\t{timing}").unwrap();
+    }
+    std::fs::write(audio_folder.join(CLIP_MANIFEST), contents).unwrap();
+}
+
+/// Adds the clips of `made` to the manifest, once ffmpeg has cut them.
+fn append_clip_manifest(
+    manifest: &Mutex<File>,
+    subs: &[Subtitle],
+    clips: &[String],
+    made: &[usize],
+) {
+    let mut lines = String::new();
+    for i in made {
+        writeln!(lines, "{}\t{}", clips[*i], manifest_timing(&subs[*i])).unwrap();
+    }
+    std::io::Write::write_all(&mut *manifest.lock().unwrap(), lines.as_bytes()).unwrap();
+}
+
+/// ffmpeg, for cutting, converting and embedding the audiobook.
+pub fn ffmpeg_command() -> Command {
+    if cfg!(unix) {
+        Command::new("ffmpeg")
+    } else if cfg!(windows) {
+        Command::new("ffmpeg.exe")
+    } else {
+        panic!("Unsupported OS possibly.")
+    }
+}
+
+/// ffprobe, for reading the audiobook's chapters.
+fn ffprobe_command() -> Command {
+    if cfg!(unix) {
+        Command::new("ffprobe")
+    } else if cfg!(windows) {
+        Command::new("ffprobe.exe")
+    } else {
+        panic!("Unsupported OS possibly.")
+    }
+}
+
+/// Puts the audiobook inside `game/audio/` so the non-split script doesn't point to the host
+/// filesystem, and returns the path the voice statements should use.
+fn embed_audiobook(audiobook: &Path, game_folder: &Path) -> String {
+    let extension = audiobook
+        .extension()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .to_lowercase();
+    let seekable = SEEKABLE_EXTENSIONS.contains(&extension.as_str());
+    let file_name = if seekable {
+        format!("audiobook.{extension}")
+    } else {
+        String::from("audiobook.mp3")
+    };
+    let mut destination = game_folder.to_path_buf();
+    destination.push("audio");
+    destination.push(&file_name);
+
+    // The path, size and modification time of the audiobook, for knowing whether the embedded
+    // one is still up to date.
+    let source = std::fs::metadata(audiobook).ok().and_then(|metadata| {
+        let modified = metadata.modified().ok()?;
+        let modified = modified.duration_since(std::time::UNIX_EPOCH).ok()?;
+        Some(format!(
+            "{}\t{}\t{}",
+            audiobook.display(),
+            metadata.len(),
+            modified.as_millis()
+        ))
+    });
+    let mut source_path = game_folder.to_path_buf();
+    source_path.push("audio");
+    source_path.push(AUDIOBOOK_SOURCE);
+    let up_to_date = destination.exists()
+        && source.is_some()
+        && std::fs::read_to_string(&source_path).ok() == source;
+    if up_to_date {
+        return format!("audio/{file_name}");
+    }
+
+    if seekable {
+        std::fs::copy(audiobook, &destination).unwrap();
+    } else {
+        let output = ffmpeg_command()
+            .args([
+                "-hide_banner",
+                "-loglevel",
+                "error",
+                "-vn",
+                "-y",
+                "-i",
+                &audiobook.to_string_lossy(),
+                "-c:a",
+                "libmp3lame",
+                "-q:a",
+                "2",
+                &destination.to_string_lossy(),
+            ])
+            .output()
+            .unwrap();
+        if !output.status.success() {
+            panic!(
+                "Failed to transcode the audiobook: {}",
+                String::from_utf8_lossy(&output.stderr)
+            );
+        }
+    }
+    if let Some(source) = source {
+        std::fs::write(source_path, source).unwrap();
+    }
+    format!("audio/{file_name}")
 }
 
 #[derive(Debug)]
@@ -125,8 +541,28 @@ pub struct MyArgs {
     pub audiobook: PathBuf,
     pub subtitle: PathBuf,
     pub epub: Option<PathBuf>,
+    pub use_epub_text: bool,
+    /// Class → style table for the epub, over what its stylesheets say.
+    pub style_classes: HashMap<String, TextStyle>,
+    /// Morphological dictionary used to add furigana the epub doesn't provide.
+    pub furigana_dictionary: Option<PathBuf>,
+    pub furigana_repeat: dictionary::FuriganaRepeat,
+    pub known_kanji: Option<PathBuf>,
+    /// The user's furigana fixes, see [`overrides::Overrides`].
+    pub furigana_overrides: Option<PathBuf>,
+    /// Reading notations to take out of the subtitles' text.
+    pub subtitle_rubies: inline_ruby::RubyNotations,
+    /// How the epub's illustrations are shown.
+    pub image_template: illustrations::ImageTemplate,
+    /// Size, fit and format the illustrations are written in.
+    pub image_export: illustrations::ImageExport,
     pub split: bool,
-    pub show_buggies: bool,
+    pub clip_layout: ClipLayout,
+    pub split_sentences: bool,
+    pub refine_splits_with_silence: bool,
+    pub merge_fragments: Option<cues::MergeThresholds>,
+    pub pack: Option<rpa::RpaGrouping>,
+    pub pack_remove_loose: bool,
     pub start_offset: i64,
     pub speed: f64,
     pub gain: f64,
@@ -134,7 +570,8 @@ pub struct MyArgs {
 
 pub fn process(args: MyArgs, thread_tx: Sender<String>) {
     dbg!(&args.audiobook);
-    let mut rubies = None;
+    let mut book = None;
+    let mut gaiji = HashMap::new();
 
     let gch = Getch::new();
     let contin = Arc::new(AtomicBool::new(true));
@@ -159,41 +596,58 @@ pub fn process(args: MyArgs, thread_tx: Sender<String>) {
         }
     });
 
-    if let Some(input_file) = args.epub {
-        let doc = EpubDoc::new(input_file);
-        assert!(doc.is_ok());
-        let mut doc = doc.unwrap();
-        let mut rubies_2: VecDeque<[String; 3]> = VecDeque::with_capacity(1000);
-
-        while doc.go_next() {
-            let current = doc.get_current_str();
-            match current {
-                Some((v, _)) => {
-                    let document = scraper::Html::parse_document(&v);
-                    let selector_ruby = Selector::parse("ruby").unwrap();
-                    for element in document.select(&selector_ruby) {
-                        let es = element.text().collect::<Vec<&str>>();
-                        let context = element.parent_element().unwrap().text().collect::<String>();
-
-                        if es.len() != 2 {
-                            dbg!(&es);
-                            println!("weirdly formated rubies idk? TODO better handling");
-                        }
+    let mut doc = None;
+    if let Some(input_file) = &args.epub {
+        let epub = EpubDoc::new(input_file);
+        assert!(epub.is_ok());
+        let mut epub = epub.unwrap();
+        let mut text = EpubText::from_epub(&mut epub, &args.style_classes);
+        for document in &text.unreadable {
+            thread_tx
+                .send(format!(
+                    "Couldn't read {} of the epub, leaving it out",
+                    document.display()
+                ))
+                .unwrap();
+        }
+        gaiji = gaiji::export_gaiji(&mut epub, &mut text.images, &args.game_folder);
+        book = Some(text);
+        doc = Some(epub);
+    }
 
-                        rubies_2.push_back([
-                            es[0].to_owned(),
-                            es[1].to_owned(),
-                            context.to_owned(),
-                        ]);
-                    }
-                }
-                None => println!("Not Found\n"),
-            }
+    let overrides = match args
+        .furigana_overrides
+        .as_deref()
+        .map(overrides::Overrides::load)
+    {
+        Some(Ok(overrides)) => Some(overrides),
+        Some(Err(err)) => {
+            thread_tx
+                .send(format!(
+                    "Couldn't read the furigana overrides, going on without them: {err}"
+                ))
+                .unwrap();
+            None
         }
-        rubies = Some(rubies_2);
+        None => None,
+    };
+    for warning in overrides.iter().flat_map(|o| &o.warnings) {
+        thread_tx.send(warning.clone()).unwrap();
+    }
+    if let (Some(book), Some(overrides)) = (&mut book, &overrides) {
+        overrides.filter_book_rubies(&mut book.rubies);
     }
 
-    let mut subs = Subtitles::parse_from_file(args.subtitle, Some("utf8")).unwrap();
+    let is_ass = args
+        .subtitle
+        .extension()
+        .is_some_and(|e| e.eq_ignore_ascii_case("ass") || e.eq_ignore_ascii_case("ssa"));
+    let mut subs = if is_ass {
+        let script = std::fs::read_to_string(&args.subtitle).unwrap();
+        ass::parse_ass(&script, args.subtitle_rubies.ass_karaoke)
+    } else {
+        Subtitles::parse_from_file(&args.subtitle, Some("utf8")).unwrap()
+    };
 
     for sub in &mut subs {
         let (a, b, c, d) = sub.start_time.get();
@@ -207,13 +661,27 @@ pub fn process(args: MyArgs, thread_tx: Sender<String>) {
     let mut subs = subs.to_vec();
 
     subs.sort();
+    if args.split_sentences {
+        thread_tx
+            .send(String::from("Splitting the subtitles into sentences"))
+            .unwrap();
+        let audiobook = args
+            .refine_splits_with_silence
+            .then_some(args.audiobook.as_path());
+        subs = cues::split_sentences(subs, audiobook);
+    }
+    if let Some(thresholds) = &args.merge_fragments {
+        subs = cues::merge_fragments(subs, thresholds);
+    }
     let mintime = Timestamp::new(0, 0, 0, args.start_offset.unsigned_abs() as u16);
 
-    std::fs::create_dir_all(format!("{}/audio", args.game_folder.display())).unwrap();
+    let mut audio_folder = args.game_folder.clone();
+    audio_folder.push("audio");
+    std::fs::create_dir_all(&audio_folder).unwrap();
 
     // Collect all subtitle text into a string.
     let mut subs_strings: Vec<String> = Vec::with_capacity(15000);
-    let mut buggies: Vec<[String; 3]> = vec![];
+    let mut unplaced: Vec<Ruby> = vec![];
     let mut subs2: Vec<Subtitle> = Vec::with_capacity(20000);
     subs.iter().tuple_windows().for_each(|(n, np1)| {
         let mut n2 = n.clone();
@@ -229,115 +697,441 @@ pub fn process(args: MyArgs, thread_tx: Sender<String>) {
     subs2.push(subs.last().unwrap().clone());
     subs_strings.push(subs.last().unwrap().text.to_owned());
 
-    if let Some(mut rubies) = rubies {
-        while !rubies.is_empty() {
-            subs_strings.iter_mut().for_each(|s| {
-                replace_rubies(s, &mut rubies);
+    let chapter_starts = audiobook_chapters(&args.audiobook);
+    let chapters = line_chapters(&subs2, &chapter_starts);
+    let mut line_spans: Vec<Vec<Span>> = vec![vec![]; subs_strings.len()];
+    let mut inline_spans = if args.subtitle_rubies.any() {
+        inline_ruby::extract_rubies(&mut subs_strings, &args.subtitle_rubies)
+    } else {
+        vec![vec![]; subs_strings.len()]
+    };
+    let mut book_alignment = None;
+    if let Some(book) = &book {
+        thread_tx
+            .send(String::from("Aligning the epub with the subtitles"))
+            .unwrap();
+        let mut alignment = LineAlignment::new(&book.text, &subs_strings);
+        if args.use_epub_text {
+            let subtitle_text = subs_strings.clone();
+            let unaligned = use_book_wording(&mut subs_strings, book, &alignment);
+            if let Err(err) = write_unaligned_report(&args.game_folder, &subs_strings, &unaligned) {
+                thread_tx
+                    .send(format!("Couldn't write unaligned_lines.txt: {err}"))
+                    .unwrap();
+            }
+            if !unaligned.is_empty() {
+                thread_tx
+                    .send(format!(
+                        "{} lines couldn't be matched with the epub and kept the subtitle's text, see unaligned_lines.txt",
+                        unaligned.len()
+                    ))
+                    .unwrap();
+            }
+            alignment = LineAlignment::new(&book.text, &subs_strings);
+            // Readings from the subtitles don't fit the lines that took the book's wording.
+            for (i, spans) in inline_spans.iter_mut().enumerate() {
+                if subtitle_text[i] != subs_strings[i] {
+                    spans.clear();
+                }
+            }
+        }
+        unplaced = place_rubies(&alignment, book, &mut line_spans);
+        book_alignment = Some(alignment);
+    }
+
+    for (spans, inline) in line_spans.iter_mut().zip(inline_spans) {
+        for span in inline {
+            markup::add_span(spans, span);
+        }
+    }
+    if let (Some(book), Some(alignment)) = (&book, &book_alignment) {
+        place_styles(alignment, book, &mut line_spans);
+        place_gaiji(alignment, book, &gaiji, &mut line_spans);
+        let (placed_notes, missed) = notes::place_notes(alignment, book, &mut line_spans);
+        if !placed_notes.is_empty() && !notes::write_notes(&args.game_folder, &placed_notes) {
+            thread_tx
+                .send(String::from(
+                    "Couldn't find the navigation in screens.rpy, show the \"book_notes_index\" screen yourself",
+                ))
+                .unwrap();
+        }
+        if missed > 0 {
+            thread_tx
+                .send(format!("{missed} note links couldn't be put in the script"))
+                .unwrap();
+        }
+    }
+    if let Some(path) = &args.furigana_dictionary {
+        thread_tx
+            .send(String::from("Adding furigana from the dictionary"))
+            .unwrap();
+        match dictionary::Dictionary::load(path) {
+            Ok(dictionary) => {
+                let known_kanji = match args
+                    .known_kanji
+                    .as_deref()
+                    .map(dictionary::load_known_kanji)
+                {
+                    Some(Ok(known_kanji)) => known_kanji,
+                    Some(Err(err)) => {
+                        thread_tx
+                            .send(format!(
+                                "Couldn't read the known kanji, taking none as known: {err}"
+                            ))
+                            .unwrap();
+                        Default::default()
+                    }
+                    None => Default::default(),
+                };
+                dictionary::add_furigana(
+                    &dictionary,
+                    &subs_strings,
+                    &chapters,
+                    args.furigana_repeat,
+                    &known_kanji,
+                    &mut line_spans,
+                );
+            }
+            Err(err) => thread_tx
+                .send(format!(
+                    "Couldn't load the dictionary, going on without it: {err}"
+                ))
+                .unwrap(),
+        }
+    }
+    if let Some(overrides) = &overrides {
+        overrides.apply(&subs_strings, &chapters, &mut line_spans);
+    }
+    // Only now, so the rubies the overrides took care of aren't reported.
+    let buggies: Vec<RubyFailure> = match &book_alignment {
+        Some(alignment) => unplaced
+            .into_iter()
+            .filter_map(|ruby| {
+                let mapped = alignment.map_span(ruby.span.clone()).map(|(line, _)| line);
+                if overrides
+                    .as_ref()
+                    .is_some_and(|o| o.settles(&ruby, mapped, &chapters))
+                {
+                    return None;
+                }
+                let (reason, line) = match mapped {
+                    Some(line) => ("overlaps another ruby", Some(line)),
+                    None => (
+                        "not found in the subtitles",
+                        alignment.nearest_line(ruby.span.clone()),
+                    ),
+                };
+                Some(RubyFailure::new(ruby, reason, line, &subs_strings))
+            })
+            .collect(),
+        None => vec![],
+    };
+
+    let clips = if args.split {
+        clip_paths(args.clip_layout, &chapters, chapter_starts.len() > 1)
+    } else {
+        vec![]
+    };
+
+    // Script blocks showing the book's illustrations, by the line they come before.
+    let mut illustrations_before: Vec<Vec<String>> = vec![vec![]; subs_strings.len() + 1];
+    if let (Some(book), Some(alignment), Some(doc)) = (&book, &book_alignment, &mut doc) {
+        let images = pages::page_images(&book.images, &book.pages);
+        let (mut placements, unanchored) = illustrations::place_illustrations(alignment, &images);
+        let fallbacks = unanchored.len();
+        let unanchored =
+            illustrations::place_unanchored(alignment, book, &mut placements, unanchored);
+        let mut files = illustrations::image_files(
+            placements.iter().map(|p| &p.image),
+            args.image_export.format,
+        );
+        let size = args
+            .image_export
+            .size
+            .unwrap_or_else(|| illustrations::game_size(&args.game_folder));
+        // Pages are written once it's known whether they share a spread.
+        let page_ids: std::collections::HashSet<String> = placements
+            .iter()
+            .filter(|p| book.pages.get(p.image.spine).copied().unwrap_or(false))
+            .filter_map(|p| p.image.id.clone())
+            .collect();
+        files.retain(|id, file| {
+            let Some((data, _)) = doc.get_resource(id) else {
+                return false;
+            };
+            if page_ids.contains(id) {
+                return true;
+            }
+            let path = args.game_folder.join(&*file);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            match illustrations::write_image(&data, &path, size, args.image_export.fit) {
+                Ok(()) => true,
+                Err(err) => {
+                    thread_tx
+                        .send(format!("Couldn't export {id}, skipping it: {err}"))
+                        .unwrap();
+                    false
+                }
+            }
+        });
+        // The cover is written by the imager, where the main menu's background goes.
+        let mut gallery = vec![];
+        if doc.get_cover_id().is_some() {
+            gallery.push(gallery::GalleryEntry {
+                file: String::from("gui/main_menu.png"),
+                caption: None,
+                unlocked: true,
             });
-            if let Some(ruby_top) = rubies.pop_front() {
-                buggies.push(ruby_top);
+        }
+        // Pages following each other at the same line, shown as one sequence.
+        let mut sequence: Option<(usize, usize, Vec<_>)> = None;
+        // Pages that couldn't be written, left out of the image names.
+        let mut failed_pages = vec![];
+        // Writes a sequence of pages by their manifest id, at half the game's width on a side of
+        // a spread and all of it alone, and returns the script showing them.
+        let mut render_pages = |pages: &[(String, Option<PageSpread>)]| {
+            let mut spreads = pages::pair_pages(pages, book.right_to_left);
+            for spread in &mut spreads {
+                spread.retain(|(id, side)| {
+                    let data = doc
+                        .get_resource(id)
+                        .map(|(data, _)| data)
+                        .unwrap_or_default();
+                    let path = args.game_folder.join(&files[id]);
+                    std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+                    let size = pages::page_size(*side, size);
+                    match illustrations::write_image(&data, &path, size, args.image_export.fit) {
+                        Ok(()) => true,
+                        Err(err) => {
+                            thread_tx
+                                .send(format!("Couldn't export {id}, skipping it: {err}"))
+                                .unwrap();
+                            failed_pages.push(id.clone());
+                            false
+                        }
+                    }
+                });
+            }
+            let spreads: Vec<Vec<(String, PageSpread)>> = spreads
+                .into_iter()
+                .filter(|spread| !spread.is_empty())
+                .map(|spread| {
+                    spread
+                        .into_iter()
+                        .map(|(id, side)| (files[&id].clone(), side))
+                        .collect()
+                })
+                .collect();
+            pages::render_spreads(&spreads, args.image_template.transition())
+        };
+        for placement in &placements {
+            let file = placement.image.id.as_ref().and_then(|id| files.get(id));
+            let is_page = book
+                .pages
+                .get(placement.image.spine)
+                .copied()
+                .unwrap_or(false);
+            let continues = matches!(&sequence, Some((line, spine, _))
+                if is_page && *line == placement.line && spine + 1 == placement.image.spine);
+            if !continues {
+                if let Some((line, _, pages)) = sequence.take() {
+                    illustrations_before[line].push(render_pages(&pages));
+                }
             }
+            match file {
+                Some(_) if is_page => {
+                    let id = placement.image.id.clone().unwrap();
+                    let spread = book.spreads.get(placement.image.spine).copied().flatten();
+                    match &mut sequence {
+                        Some((_, spine, pages)) => {
+                            *spine = placement.image.spine;
+                            pages.push((id, spread));
+                        }
+                        None => {
+                            sequence =
+                                Some((placement.line, placement.image.spine, vec![(id, spread)]))
+                        }
+                    }
+                }
+                Some(file) => {
+                    if !gallery.iter().any(|e| &e.file == file) {
+                        gallery.push(gallery::GalleryEntry {
+                            file: file.clone(),
+                            caption: book
+                                .chapter_titles
+                                .get(placement.image.spine)
+                                .cloned()
+                                .flatten(),
+                            unlocked: false,
+                        });
+                    }
+                    illustrations_before[placement.line].push(gallery::unlock_line(file));
+                    illustrations_before[placement.line].push(args.image_template.render(file))
+                }
+                None if placement.image.id.is_none() => thread_tx
+                    .send(format!(
+                        "{} isn't in the epub's manifest, skipping it",
+                        placement.image.path.display()
+                    ))
+                    .unwrap(),
+                None => {}
+            }
+        }
+        if let Some((line, _, pages)) = sequence {
+            illustrations_before[line].push(render_pages(&pages));
+        }
+        for id in failed_pages {
+            files.remove(&id);
+        }
+        // Not worth a screen for the cover alone, the only entry unlocked from the start.
+        if gallery.iter().any(|e| !e.unlocked)
+            && !gallery::write_gallery(&args.game_folder, &gallery)
+        {
+            thread_tx
+                .send(String::from(
+                    "Couldn't find the navigation in screens.rpy, show the \"illustration_gallery\" screen yourself",
+                ))
+                .unwrap();
+        }
+        let mut project_folder = args.game_folder.clone();
+        project_folder.pop();
+        illustrations::write_image_names(&project_folder, &files, &book.images);
+        illustrations::write_placement_report(
+            &project_folder,
+            &placements,
+            &unanchored,
+            &subs_strings,
+        );
+        if fallbacks > unanchored.len() {
+            thread_tx
+                .send(format!(
+                    "{} images were placed by their chapter, see image_placements.txt",
+                    fallbacks - unanchored.len()
+                ))
+                .unwrap();
+        }
+        if !unanchored.is_empty() {
+            thread_tx
+                .send(format!(
+                    "{} images couldn't be placed in the script, see image_placements.txt",
+                    unanchored.len()
+                ))
+                .unwrap();
         }
     }
 
+    let embedded_audiobook = if args.split {
+        None
+    } else {
+        thread_tx
+            .send(String::from("Copying the audiobook into the game folder"))
+            .unwrap();
+        Some(embed_audiobook(&args.audiobook, &args.game_folder))
+    };
+
     let mut res = String::from("");
     let head = std::fs::read_to_string("top.txt").expect("Error reading the script top");
     writeln!(res, "{}", head).unwrap();
     writeln!(res, "label start:").unwrap();
     subs2.iter().enumerate().for_each(|(i, s)| {
+        for block in &illustrations_before[i] {
+            res.push_str(block);
+        }
         if i % 10 == 0 {
             writeln!(res, "    $renpy.force_autosave()").unwrap();
         }
         if args.split {
-            writeln!(res, "    voice \"audiobook-{}.mp3\"", i).unwrap();
-        } else {
-            writeln!(
-                res,
-                "    voice \"{}{}\"",
-                subtime_to_renpy(s),
-                &args.audiobook.display()
-            )
-            .unwrap();
+            writeln!(res, "    voice \"audio/{}\"", clips[i]).unwrap();
+        } else if let Some(audiobook) = &embedded_audiobook {
+            writeln!(res, "    voice \"{}{}\"", subtime_to_renpy(s), audiobook).unwrap();
         }
-        writeln!(res, "    \"{}\"", subs_strings[i]).unwrap();
+        writeln!(
+            res,
+            "    \"{}\"",
+            markup::render(&subs_strings[i], &line_spans[i])
+        )
+        .unwrap();
     });
+    for block in &illustrations_before[subs2.len()] {
+        res.push_str(block);
+    }
     writeln!(res, "return").unwrap();
 
-    if !buggies.is_empty() {
-        if args.show_buggies {
-            println!("{:?}", buggies);
-        } else {
-            println!(
-                "Some({}) ruby failed to be inserted in, use --show-bugies to display them (beware spoilers)",
-                buggies.len()
-            );
-        }
+    let mut project_folder = args.game_folder.clone();
+    project_folder.pop();
+    if buggies.is_empty() {
+        // A report left by an earlier run would otherwise look current.
+        ruby_report::remove_ruby_report(&project_folder);
+    } else {
+        let report = ruby_report::write_ruby_report(&project_folder, &buggies).unwrap();
+        thread_tx
+            .send(format!(
+                "{} rubies couldn't be placed, see {} (beware spoilers)",
+                buggies.len(),
+                report.display()
+            ))
+            .unwrap();
     }
 
     let mut file = File::create(format!("{}/script.rpy", args.game_folder.display())).unwrap();
     use std::io::Write;
     file.write_all(res.as_bytes()).unwrap();
     if args.split {
+        for clip in &clips {
+            let mut folder = audio_folder.clone();
+            folder.push(clip);
+            folder.pop();
+            std::fs::create_dir_all(folder).unwrap();
+        }
+        let mut manifest = read_clip_manifest(&audio_folder);
+        remove_stale_clips(&audio_folder, &clips, args.clip_layout, &mut manifest);
+        let manifest_file = Mutex::new(
+            std::fs::OpenOptions::new()
+                .append(true)
+                .open(audio_folder.join(CLIP_MANIFEST))
+                .unwrap(),
+        );
         let n = AtomicUsize::new(0);
         let m = subs.len();
         subs2
             .chunks(CHUNK_SIZE)
-            .enumerate()
+            .zip(clips.chunks(CHUNK_SIZE))
             .par_bridge()
             // .par_chunks()
-            .for_each(move |(i, s)| {
+            .for_each(|(s, c)| {
                 let size = s.len();
-                let prepared =
-                    prepare_ffmpeg_command(i * size, size, s, &args.game_folder.to_string_lossy()); //TODO
+                let (prepared, mut made) = prepare_ffmpeg_command(s, c, &audio_folder, &manifest);
                 if !contin.load(Ordering::Relaxed) {
                     return;
                 }
-                let mut command = if cfg!(unix) {
-                    Command::new("ffmpeg")
-                } else if cfg!(windows) {
-                    Command::new("cmd")
-                } else {
-                    panic!("Unsupported OS possibly.")
-                };
-                let args: Vec<String> = if cfg!(unix) {
-                    [
-                        "-hide_banner".to_string(),
-                        "-loglevel".to_string(),
-                        "error".to_string(),
-                        "-vn".to_string(),
-                        "-y".to_string(),
-                        "-i".to_string(),
-                        args.audiobook.to_string_lossy().to_string(),
-                    ]
-                    .iter()
-                    .chain(prepared.iter())
-                    .cloned()
-                    .collect()
-                } else if cfg!(windows) {
-                    [
-                        "-/C".to_string(),
-                        "ffmpeg.exe".to_string(),
-                        "-hide_banner".to_string(),
-                        "-loglevel".to_string(),
-                        "error".to_string(),
-                        "-vn".to_string(),
-                        "-y".to_string(),
-                        "-i".to_string(),
-                        args.audiobook.to_string_lossy().to_string(),
-                    ]
-                    .iter()
-                    .chain(prepared.iter())
-                    .cloned()
-                    .collect()
-                } else {
-                    panic!("Unsupported OS possibly.")
-                };
-
-                let _ = command.args(&args).output().unwrap();
+                if !prepared.is_empty() {
+                    let output = ffmpeg_command()
+                        .args(["-hide_banner", "-loglevel", "error", "-vn", "-y", "-i"])
+                        .arg(&args.audiobook)
+                        .args(&prepared)
+                        .output()
+                        .unwrap();
+                    if !output.status.success() {
+                        // Left out of the manifest, and removed so they're not taken as cut.
+                        for i in &made {
+                            let _ = std::fs::remove_file(audio_folder.join(&c[*i]));
+                        }
+                        thread_tx
+                            .send(format!(
+                                "ffmpeg couldn't cut {} to {}: {}\n",
+                                c[0],
+                                c[c.len() - 1],
+                                String::from_utf8_lossy(&output.stderr)
+                            ))
+                            .unwrap();
+                        made.clear();
+                    }
+                }
+                append_clip_manifest(&manifest_file, s, c, &made);
                 // dbg!(child);
                 n.fetch_add(size, std::sync::atomic::Ordering::Relaxed);
                 thread_tx.send(format!("{n:?}/{m} completed!\n")).unwrap();
                 println!("{n:?}/{m} completed!");
-            })
+            });
     }
 }
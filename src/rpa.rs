@@ -0,0 +1,171 @@
+use flate2::{write::ZlibEncoder, Compression};
+use std::{
+    fs::File,
+    io::{self, BufWriter, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use super::{AUDIOBOOK_SOURCE, CLIP_MANIFEST};
+
+/// Folders of `game/` that get packed, with the archive they go to when grouping by type.
+const ASSET_FOLDERS: [(&str, &str); 2] = [("audio", "audio.rpa"), ("images", "images.rpa")];
+const SINGLE_ARCHIVE: &str = "archive.rpa";
+/// `RPA-3.0 <16 hex digits> <8 hex digits>\n`
+const HEADER_LEN: u64 = 34;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RpaGrouping {
+    /// Everything in `archive.rpa`.
+    Single,
+    /// `audio.rpa` and `images.rpa`.
+    ByAssetType,
+}
+
+fn collect_files(folder: &Path, files: &mut Vec<PathBuf>) -> io::Result<()> {
+    if !folder.is_dir() {
+        return Ok(());
+    }
+    for entry in std::fs::read_dir(folder)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            collect_files(&path, files)?;
+        } else if path
+            .file_name()
+            .is_some_and(|n| n != CLIP_MANIFEST && n != AUDIOBOOK_SOURCE)
+        {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Name Ren'Py looks the file up with: relative to `game/`, forward slashes.
+fn archive_name(game_folder: &Path, file: &Path) -> String {
+    file.strip_prefix(game_folder)
+        .unwrap_or(file)
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+fn pickle_int(out: &mut Vec<u8>, value: u64) {
+    if value < 0x100 {
+        out.push(b'K');
+        out.push(value as u8);
+    } else if value < 0x1_0000 {
+        out.push(b'M');
+        out.extend((value as u16).to_le_bytes());
+    } else if value < 0x8000_0000 {
+        out.push(b'J');
+        out.extend((value as i32).to_le_bytes());
+    } else {
+        // LONG1: little endian two's complement, with a spare byte so the sign bit stays clear.
+        let mut bytes = value.to_le_bytes().to_vec();
+        while bytes.len() > 1 && bytes[bytes.len() - 1] == 0 && bytes[bytes.len() - 2] < 0x80 {
+            bytes.pop();
+        }
+        if bytes[bytes.len() - 1] >= 0x80 {
+            bytes.push(0);
+        }
+        out.push(0x8a);
+        out.push(bytes.len() as u8);
+        out.extend(bytes);
+    }
+}
+
+/// Pickles (protocol 2) the `{name: [(offset ^ key, length ^ key)]}` index Ren'Py expects.
+fn pickle_index(entries: &[(String, u64, u64)], key: u64) -> Vec<u8> {
+    let mut out = vec![0x80, 2, b'}', b'('];
+    for (name, offset, length) in entries {
+        out.push(b'X');
+        out.extend((name.len() as u32).to_le_bytes());
+        out.extend(name.as_bytes());
+        out.push(b']');
+        pickle_int(&mut out, offset ^ key);
+        pickle_int(&mut out, length ^ key);
+        out.push(0x86); // TUPLE2
+        out.push(b'a'); // APPEND
+    }
+    out.push(b'u'); // SETITEMS
+    out.push(b'.');
+    out
+}
+
+/// Writes a RPA-3.0 archive of `files`, returning how many bytes were packed.
+pub fn write_archive(game_folder: &Path, files: &[PathBuf], archive: &Path) -> io::Result<u64> {
+    let key = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| u64::from(d.subsec_nanos() ^ d.as_secs() as u32))
+        .unwrap_or(0x4242_4242);
+    let mut writer = BufWriter::new(File::create(archive)?);
+    writer.write_all(&[b' '; HEADER_LEN as usize])?;
+
+    let mut entries = Vec::with_capacity(files.len());
+    let mut offset = HEADER_LEN;
+    for file in files {
+        let length = io::copy(&mut File::open(file)?, &mut writer)?;
+        entries.push((archive_name(game_folder, file), offset, length));
+        offset += length;
+    }
+
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(&pickle_index(&entries, key))?;
+    writer.write_all(&encoder.finish()?)?;
+
+    writer.seek(SeekFrom::Start(0))?;
+    writeln!(writer, "RPA-3.0 {offset:016x} {key:08x}")?;
+    writer.flush()?;
+    Ok(offset - HEADER_LEN)
+}
+
+fn remove_empty_folders(folder: &Path) {
+    if let Ok(entries) = std::fs::read_dir(folder) {
+        for entry in entries.flatten() {
+            if entry.path().is_dir() {
+                remove_empty_folders(&entry.path());
+            }
+        }
+    }
+    let _ = std::fs::remove_dir(folder);
+}
+
+/// Packs `game/audio` and `game/images` into `.rpa` archives next to them, optionally deleting
+/// the loose files afterwards. Returns the archives that were written.
+pub fn pack_game(
+    game_folder: &Path,
+    grouping: RpaGrouping,
+    remove_loose: bool,
+) -> io::Result<Vec<PathBuf>> {
+    let mut groups: Vec<(&str, Vec<PathBuf>)> = vec![];
+    for (folder, archive) in ASSET_FOLDERS {
+        let mut files = vec![];
+        collect_files(&game_folder.join(folder), &mut files)?;
+        files.sort();
+        match (grouping, groups.last_mut()) {
+            (RpaGrouping::Single, Some((_, all))) => all.extend(files),
+            (RpaGrouping::Single, None) => groups.push((SINGLE_ARCHIVE, files)),
+            (RpaGrouping::ByAssetType, _) => groups.push((archive, files)),
+        }
+    }
+
+    let mut written = vec![];
+    for (archive, files) in groups.into_iter().filter(|(_, f)| !f.is_empty()) {
+        let path = game_folder.join(archive);
+        write_archive(game_folder, &files, &path)?;
+        if remove_loose {
+            for file in &files {
+                std::fs::remove_file(file)?;
+            }
+        }
+        written.push(path);
+    }
+
+    if remove_loose {
+        for (folder, _) in ASSET_FOLDERS {
+            remove_empty_folders(&game_folder.join(folder));
+        }
+    }
+    Ok(written)
+}
@@ -0,0 +1,159 @@
+use std::fmt::Write;
+use std::io;
+use std::path::Path;
+
+use super::epub_text::Ruby;
+
+/// How many lines around the spot a ruby should have gone to are shown.
+const NEARBY_LINES: usize = 1;
+
+/// A ruby of the book that didn't make it into the script.
+#[derive(Debug, Clone)]
+pub struct RubyFailure {
+    pub ruby: Ruby,
+    pub reason: &'static str,
+    /// Script lines (index, text) around where the ruby should have been.
+    pub nearest: Vec<(usize, String)>,
+}
+
+impl RubyFailure {
+    pub fn new(ruby: Ruby, reason: &'static str, line: Option<usize>, lines: &[String]) -> Self {
+        let nearest = match line {
+            Some(line) => (line.saturating_sub(NEARBY_LINES)
+                ..(line + NEARBY_LINES + 1).min(lines.len()))
+                .map(|i| (i, lines[i].clone()))
+                .collect(),
+            None => vec![],
+        };
+        Self {
+            ruby,
+            reason,
+            nearest,
+        }
+    }
+}
+
+fn json_string(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len() + 2);
+    escaped.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if (c as u32) < 0x20 => write!(escaped, "\\u{:04x}", c as u32).unwrap(),
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+fn html_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn to_json(failures: &[RubyFailure]) -> String {
+    let mut json = String::from("[\n");
+    for (n, failure) in failures.iter().enumerate() {
+        let nearest = failure
+            .nearest
+            .iter()
+            .map(|(line, text)| {
+                format!(
+                    "{{\"line\": {}, \"text\": {}}}",
+                    line + 1,
+                    json_string(text)
+                )
+            })
+            .collect::<Vec<_>>()
+            .join(", ");
+        write!(
+            json,
+            "  {{\"base\": {}, \"reading\": {}, \"reason\": {}, \"book_span\": [{}, {}], \"context\": {}, \"nearest_lines\": [{}]}}",
+            json_string(&failure.ruby.base),
+            json_string(&failure.ruby.reading),
+            json_string(failure.reason),
+            failure.ruby.span.start,
+            failure.ruby.span.end,
+            json_string(&failure.ruby.context),
+            nearest
+        )
+        .unwrap();
+        json.push_str(if n + 1 < failures.len() { ",\n" } else { "\n" });
+    }
+    json.push_str("]\n");
+    json
+}
+
+fn to_html(failures: &[RubyFailure]) -> String {
+    let mut html = String::from(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>Rubies that couldn't be placed</title>\n\
+         <style>body{font-family:sans-serif}td{border-top:1px solid #ccc;padding:4px;vertical-align:top}\
+         ruby{font-size:1.4em}mark{background:#fd6}.line{color:#888}</style>\n</head>\n<body>\n",
+    );
+    writeln!(
+        html,
+        "<h1>{} rubies couldn't be placed</h1>\n<p>Paragraphs from the book are folded to keep spoilers out of sight.</p>\n<table>\n<tr><th>Ruby</th><th>Why</th><th>Nearest lines</th><th>Book</th></tr>",
+        failures.len()
+    )
+    .unwrap();
+    for failure in failures {
+        let ruby = &failure.ruby;
+        let nearest = if failure.nearest.is_empty() {
+            String::from("(nowhere near anything)")
+        } else {
+            failure
+                .nearest
+                .iter()
+                .map(|(line, text)| {
+                    format!(
+                        "<span class=\"line\">{}</span> {}",
+                        line + 1,
+                        html_escape(text)
+                    )
+                })
+                .collect::<Vec<_>>()
+                .join("<br>")
+        };
+        let context = html_escape(&ruby.context).replacen(
+            &html_escape(&ruby.base),
+            &format!("<mark>{}</mark>", html_escape(&ruby.base)),
+            1,
+        );
+        writeln!(
+            html,
+            "<tr><td><ruby>{}<rt>{}</rt></ruby></td><td>{}</td><td>{}</td><td><details><summary>Paragraph</summary>{}</details></td></tr>",
+            html_escape(&ruby.base),
+            html_escape(&ruby.reading),
+            failure.reason,
+            nearest,
+            context
+        )
+        .unwrap();
+    }
+    html.push_str("</table>\n</body>\n</html>\n");
+    html
+}
+
+/// Writes `ruby_report.json` and `ruby_report.html` in `folder`, returning the HTML's path.
+pub fn write_ruby_report(
+    folder: &Path,
+    failures: &[RubyFailure],
+) -> io::Result<std::path::PathBuf> {
+    std::fs::write(folder.join("ruby_report.json"), to_json(failures))?;
+    let html = folder.join("ruby_report.html");
+    std::fs::write(&html, to_html(failures))?;
+    Ok(html)
+}
+
+/// Removes the report of an earlier run, when nothing failed this time. Missing files are fine.
+pub fn remove_ruby_report(folder: &Path) {
+    let _ = std::fs::remove_file(folder.join("ruby_report.json"));
+    let _ = std::fs::remove_file(folder.join("ruby_report.html"));
+}
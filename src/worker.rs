@@ -2,7 +2,7 @@ use std::{
     ffi::OsStr,
     io::Read,
     path::{Path, PathBuf},
-    process::{Command, Stdio},
+    process::Stdio,
     sync::mpsc::{self, Receiver, Sender},
     thread,
 };
@@ -13,7 +13,7 @@ use relm4::{ComponentSender, Worker};
 use crate::AppInMsg;
 use crate::{
     epub_process,
-    process::{process, MyArgs},
+    process::{ffmpeg_command, process, rpa, MyArgs},
 };
 
 pub struct AsyncHandler;
@@ -25,16 +25,6 @@ pub enum AsyncHandlerInMsg {
 }
 
 impl AsyncHandler {
-    fn create_command() -> Command {
-        if cfg!(unix) {
-            Command::new("ffmpeg")
-        } else if cfg!(windows) {
-            Command::new("ffmpeg.exe")
-        } else {
-            panic!("Unsupported OS possibly.")
-        }
-    }
-
     fn update_buffer(contents: &str, clear: bool, sender: &ComponentSender<Self>) {
         sender
             .output(AppInMsg::UpdateBuffer(contents.to_string(), clear))
@@ -74,7 +64,7 @@ impl AsyncHandler {
                 false,
                 sender,
             );
-            let mut command = AsyncHandler::create_command();
+            let mut command = ffmpeg_command();
             command.stdout(Stdio::piped()).stderr(Stdio::piped()).args([
                 "-stats",
                 "-v",
@@ -165,21 +155,34 @@ impl AsyncHandler {
         thread::spawn(move || {
             let game_folder = args.game_folder.clone();
             let epub = args.epub.clone();
+            let pack = args.pack;
+            let pack_remove_loose = args.pack_remove_loose;
             process(args, thread_tx.clone());
             if let Some(ep) = epub {
-                thread_tx
-                    .send(String::from("Trying to insert epub images in the script"))
-                    .unwrap();
-                let mut epubimager = epub_process::EpubImager::new(ep, game_folder);
-                let remaining = epubimager.do_the_epub_thing();
-                if remaining > 0 {
+                let mut epubimager = epub_process::EpubImager::new(ep, game_folder.clone());
+                epubimager.write_cover(&thread_tx);
+                let name = game_folder.parent().and_then(Path::file_name);
+                if let Err(err) =
+                    epubimager.write_metadata(&name.unwrap_or_default().to_string_lossy())
+                {
                     thread_tx
-                        .send(format!(
-                            "{remaining} images were not implemented in the script"
-                        ))
+                        .send(format!("Couldn't write the book's metadata: {err}"))
                         .unwrap();
                 }
             }
+            if let Some(grouping) = pack {
+                thread_tx
+                    .send(String::from("Packing the assets into .rpa archives"))
+                    .unwrap();
+                match rpa::pack_game(&game_folder, grouping, pack_remove_loose) {
+                    Ok(archives) => thread_tx
+                        .send(format!("{} archives written", archives.len()))
+                        .unwrap(),
+                    Err(err) => thread_tx
+                        .send(format!("Packing the archives failed: {err}"))
+                        .unwrap(),
+                }
+            }
 
             thread_tx.send(String::from("STOP")).unwrap();
         });